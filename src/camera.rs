@@ -0,0 +1,79 @@
+// camera.rs
+
+use crate::gfx::Vector2;
+use crate::player::Player;
+
+// Pinhole camera built from the player's pose. Screen columns map to
+// evenly spaced points on the camera plane (not evenly spaced angles),
+// which is what keeps straight walls straight.
+pub struct Camera {
+  pub pos: Vector2,
+  pub dir: Vector2,   // unit view direction
+  pub plane: Vector2, // perpendicular to dir, length tan(fov / 2)
+  pub projection_distance: f32, // distance to the projection plane, in pixels
+  pub horizon: f32,             // screen row of the horizon, moved by the player's pitch
+  pub eye: f32,                 // eye height above the floor, in blocks
+  width: f32,
+}
+
+impl Camera {
+  pub fn new(player: &Player, width: u32, height: u32) -> Self {
+    let half_fov_tan = (player.fov / 2.0).tan();
+    let dir = Vector2::new(player.a.cos(), player.a.sin());
+    let plane = Vector2::new(-dir.y * half_fov_tan, dir.x * half_fov_tan);
+
+    Camera {
+      pos: player.pos,
+      dir,
+      plane,
+      projection_distance: (width as f32 / 2.0) / half_fov_tan,
+      // pitch shears the view instead of rotating it, so walls stay vertical
+      horizon: height as f32 * (0.5 + player.pitch),
+      eye: player.eye_height(),
+      width: width as f32,
+    }
+  }
+
+  // -1.0 at the left edge of the screen, 1.0 at the right edge
+  fn camera_x(&self, column: f32) -> f32 {
+    2.0 * column / self.width - 1.0
+  }
+
+  pub fn ray_dir(&self, column: u32) -> Vector2 {
+    let cx = self.camera_x(column as f32 + 0.5);
+    Vector2::new(self.dir.x + self.plane.x * cx, self.dir.y + self.plane.y * cx)
+  }
+
+  pub fn ray_angle(&self, column: u32) -> f32 {
+    let ray = self.ray_dir(column);
+    ray.y.atan2(ray.x)
+  }
+
+  // Distance along the view direction, used instead of the Euclidean
+  // distance to avoid the fisheye effect.
+  pub fn depth(&self, point: Vector2) -> f32 {
+    (point.x - self.pos.x) * self.dir.x + (point.y - self.pos.y) * self.dir.y
+  }
+
+  // On-screen size of something `size` world units tall at `depth`
+  pub fn project_size(&self, size: f32, depth: f32) -> f32 {
+    size * self.projection_distance / depth
+  }
+
+  // Project a world point to (screen x, depth). Returns None for points
+  // behind the camera.
+  pub fn project(&self, point: Vector2) -> Option<(f32, f32)> {
+    let depth = self.depth(point);
+    if depth <= 0.0 {
+      return None;
+    }
+
+    let dx = point.x - self.pos.x;
+    let dy = point.y - self.pos.y;
+    let plane_len = (self.plane.x * self.plane.x + self.plane.y * self.plane.y).sqrt();
+    let lateral = (dx * self.plane.x + dy * self.plane.y) / plane_len;
+
+    let screen_x = self.width / 2.0 + (lateral / depth) * self.projection_distance;
+    Some((screen_x, depth))
+  }
+}
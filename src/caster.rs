@@ -1,65 +1,438 @@
 // caster.rs
 
-use raylib::color::Color;
-
+use crate::doors::Doors;
 use crate::framebuffer::Framebuffer;
-use crate::maze::Maze;
+use crate::gfx::{Color, Vector2};
+use crate::line::line;
+use crate::maze::{LOW_CEILING, Level, is_low_ceiling, is_mirror, is_see_through, is_wall};
 use crate::player::Player;
+use crate::portals::MAX_PORTAL_CROSSINGS;
+
+// Which side of the wall cell the ray hit. The maze grows down in y,
+// so North is the side facing row 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Face {
+  North,
+  South,
+  East,
+  West,
+}
+
+impl Face {
+  // East and West faces lie on vertical grid lines
+  pub fn is_vertical(self) -> bool {
+    matches!(self, Face::East | Face::West)
+  }
+
+  // Face from its name in level and manifest files
+  pub fn from_name(name: &str) -> Option<Face> {
+    match name {
+      "north" => Some(Face::North),
+      "south" => Some(Face::South),
+      "east" => Some(Face::East),
+      "west" => Some(Face::West),
+      _ => None,
+    }
+  }
+
+  // Unit vector pointing out of the face
+  pub fn normal(self) -> Vector2 {
+    match self {
+      Face::North => Vector2::new(0.0, -1.0),
+      Face::South => Vector2::new(0.0, 1.0),
+      Face::East => Vector2::new(1.0, 0.0),
+      Face::West => Vector2::new(-1.0, 0.0),
+    }
+  }
+}
+
+// Where a ray went on from a mirror or portal, in world units
+#[derive(Clone, Copy)]
+pub struct Onward {
+  pub pos: Vector2,
+  pub dir: Vector2, // unit length
+  pub bounces: u32, // mirrors bounced off so far, this one included
+}
 
 pub struct Intersect {
-  pub distance: f32,
+  pub distance: f32, // along the ray, through any mirrors it bounced off
   pub impact: char,
+  pub cell: (usize, usize), // cell of the wall that was hit
   pub hit_x: f32,
   pub hit_y: f32,
-  pub vertical_side: bool,
+  pub face: Face,
+  pub tex_u: f32, // horizontal texture coordinate in [0, 1), left to right as seen
+  pub height: f32,        // height of the wall that was hit, in blocks
+  pub bottom: f32,        // height of its underside: 0, unless it hangs from the ceiling or only its top shows
+  pub exit_distance: f32, // where the ray leaves the hit cell, for wall tops
+  pub bounces: u32,       // mirrors the ray bounced off before this hit
+  pub onward: Option<Onward>, // a mirror or portal the ray went on from; what it saw comes before it
+}
+
+impl Intersect {
+  fn miss(origin: Vector2) -> Self {
+    Intersect {
+      distance: f32::MAX,
+      impact: ' ',
+      cell: (0, 0),
+      hit_x: origin.x,
+      hit_y: origin.y,
+      face: Face::North,
+      tex_u: 0.0,
+      height: 0.0,
+      bottom: 0.0,
+      exit_distance: f32::MAX,
+      bounces: 0,
+      onward: None,
+    }
+  }
 }
 
+// Hits are capped so a long run of low walls cannot stall a column
+const MAX_HITS: usize = 16;
+
+// First wall along the ray, optionally drawing the ray into the 2D view
 pub fn cast_ray(
   framebuffer: &mut Framebuffer,
-  maze: &Maze,
+  level: &Level,
+  doors: &Doors,
   player: &Player,
   a: f32,
   block_size: usize,
   draw_line: bool,
 ) -> Intersect {
-  let mut d = 0.0;
-  let mut prev_i: Option<usize> = None;
-  let mut prev_j: Option<usize> = None;
-
   framebuffer.set_current_color(Color::WHITESMOKE);
 
-  loop {
-    let cos = d * a.cos();
-    let sin = d * a.sin();
-    let x = (player.pos.x + cos) as isize;
-    let y = (player.pos.y + sin) as isize;
+  let first = trace(level, doors, player.pos, a, block_size, 1)
+    .into_iter()
+    .next()
+    .unwrap_or_else(|| Intersect::miss(player.pos));
+
+  if draw_line && first.distance < f32::MAX {
+    line(framebuffer, player.pos, Vector2::new(first.hit_x, first.hit_y));
+  }
+  first
+}
+
+// Every wall along the ray, nearest first, up to the first opaque one
+// that is at least as tall as the tallest wall of the level: nothing
+// behind it can show over its top. See-through tiles never stop the ray,
+// mirrors send it on in the reflected direction and portals out of the
+// other end of the portal.
+pub fn cast_ray_all(
+  level: &Level,
+  doors: &Doors,
+  origin: Vector2,
+  a: f32,
+  block_size: usize,
+) -> Vec<Intersect> {
+  trace(level, doors, origin, a, block_size, MAX_HITS)
+}
+
+// Part of a ray between two mirrors or portals, in cell units
+struct Ray {
+  pos: Vector2,
+  dir: Vector2,
+  cell: (isize, isize), // cell the ray starts in
+  travelled: f32,       // ray length before `pos`
+  bounces: u32,         // mirrors so far
+  crossings: u32,       // portals so far
+}
+
+fn trace(
+  level: &Level,
+  doors: &Doors,
+  origin: Vector2,
+  a: f32,
+  block_size: usize,
+  max_hits: usize,
+) -> Vec<Intersect> {
+  let block = block_size as f32;
+  let mut hits = Vec::new();
+
+  let pos = Vector2::new(origin.x / block, origin.y / block);
+  if pos.x < 0.0 || pos.y < 0.0 {
+    return hits;
+  }
+
+  let mut ray = Some(Ray {
+    pos,
+    dir: Vector2::new(a.cos(), a.sin()),
+    cell: (pos.x as isize, pos.y as isize),
+    travelled: 0.0,
+    bounces: 0,
+    crossings: 0,
+  });
+  while let Some(current) = ray {
+    ray = walk(level, doors, &current, block, max_hits, &mut hits);
+  }
+
+  hits
+}
 
-    if x < 0 || y < 0 { return Intersect{ distance: f32::MAX, impact: ' ', hit_x: player.pos.x, hit_y: player.pos.y, vertical_side: false }; }
-    let i = (x as usize) / block_size;
-    let j = (y as usize) / block_size;
+// Grid DDA (Amanatides & Woo): walk the ray from cell boundary to cell
+// boundary, so every crossed cell is visited exactly once and the hit
+// distance is exact. Doors are thin panels through the middle of their
+// cell, slid sideways by how open they are; thin and diagonal walls are
+// segments intersected inside the cells that hold them. Returns the
+// ray that carries on when the walk ends on a mirror or portal.
+fn walk(
+  level: &Level,
+  doors: &Doors,
+  ray: &Ray,
+  block: f32,
+  max_hits: usize,
+  hits: &mut Vec<Intersect>,
+) -> Option<Ray> {
+  let maze = &level.maze;
+  let (pos_x, pos_y) = (ray.pos.x, ray.pos.y);
+  let (dir_x, dir_y) = (ray.dir.x, ray.dir.y);
+  let (mut i, mut j) = ray.cell;
 
-    if j >= maze.len() || i >= maze[0].len() {
-      return Intersect{ distance: f32::MAX, impact: ' ', hit_x: player.pos.x, hit_y: player.pos.y, vertical_side: false };
+  // ray length (in cells) needed to cross one whole cell on each axis
+  let delta_x = if dir_x == 0.0 { f32::INFINITY } else { (1.0 / dir_x).abs() };
+  let delta_y = if dir_y == 0.0 { f32::INFINITY } else { (1.0 / dir_y).abs() };
+
+  // ray length to the first vertical / horizontal grid line
+  let (step_i, mut side_x) = if dir_x < 0.0 {
+    (-1, (pos_x - i as f32) * delta_x)
+  } else {
+    (1, (i as f32 + 1.0 - pos_x) * delta_x)
+  };
+  let (step_j, mut side_y) = if dir_y < 0.0 {
+    (-1, (pos_y - j as f32) * delta_y)
+  } else {
+    (1, (j as f32 + 1.0 - pos_y) * delta_y)
+  };
+
+  // height of the wall cell the ray is travelling through, if any
+  let mut inside_wall: Option<f32> = None;
+  // whether the ray is under a run of low ceiling cells, which is one
+  // block with a face only where the ray goes in. A ray from the viewer
+  // starts under the one they stand in rather than at its face.
+  let mut inside_low_ceiling = ray.travelled == 0.0;
+
+  // where the ray entered the current cell, and through which face; the
+  // first cell is the one the ray starts in
+  let mut t = 0.0;
+  let mut face = Face::North;
+
+  while hits.len() < max_hits {
+    if i < 0 || j < 0 || j as usize >= maze.len() || i as usize >= maze[j as usize].len() {
+      break;
     }
 
-    if maze[j][i] != ' ' && maze[j][i] != 'g' {
-      let hit_x = player.pos.x + (d * a.cos());
-      let hit_y = player.pos.y + (d * a.sin());
-      let vertical_side = if let (Some(pi), Some(_pj)) = (prev_i, prev_j) {
-        i != pi
-      } else { false };
-      return Intersect{ distance: d, impact: maze[j][i], hit_x, hit_y, vertical_side };
+    let cell = maze[j as usize][i as usize];
+    // the ray leaves this cell at the next grid line
+    let t_exit = side_x.min(side_y);
+    // mirrors and portals only pass on rays that reach them through open space
+    let from_open = inside_wall.is_none();
+    let mut portal = None;
+    // a wall behind one at least as tall, of which only the top can show
+    let mut top_only = false;
+
+    // distance, exit distance, position along the face that was hit in
+    // [0, 1), face and tile
+    let mut hit = None;
+    if let Some(door) = doors.get(i as usize, j as usize) {
+      let (t_door, along, face) = if door.horizontal {
+        let t_door = (j as f32 + 0.5 - pos_y) / dir_y;
+        (t_door, pos_x + t_door * dir_x - i as f32, if dir_y > 0.0 { Face::North } else { Face::South })
+      } else {
+        let t_door = (i as f32 + 0.5 - pos_x) / dir_x;
+        (t_door, pos_y + t_door * dir_y - j as f32, if dir_x > 0.0 { Face::West } else { Face::East })
+      };
+      // the open part of the doorway is [0, open) along the panel
+      if t_door >= t && t_door <= t_exit && along >= door.open && along < 1.0 {
+        hit = Some((t_door, t_door, along - door.open, face, cell));
+      }
+      inside_wall = None;
+    } else if is_see_through(cell) {
+      // drawn on the face the ray enters by; what lies behind shows
+      // through the holes of the texture
+      let along = if face.is_vertical() { pos_y + t * dir_y } else { pos_x + t * dir_x };
+      hit = Some((t, t_exit, along.fract(), face, cell));
+      inside_wall = None;
+    } else if is_low_ceiling(cell) {
+      // hangs from the ceiling; the ray goes on below it
+      if !inside_low_ceiling {
+        let along = if face.is_vertical() { pos_y + t * dir_y } else { pos_x + t * dir_x };
+        hit = Some((t, t_exit, along.fract(), face, cell));
+      }
+      inside_wall = None;
+    } else if is_wall(cell) {
+      // going from a wall into a wall, only the part of the new one that
+      // rises above the old one is a visible face
+      let height = level.wall_height(i as usize, j as usize);
+      let along = if face.is_vertical() { pos_y + t * dir_y } else { pos_x + t * dir_x };
+      match inside_wall {
+        // a run of walls of one height has one top, out to where the ray
+        // leaves the run; the wall before this one was the last hit
+        Some(inside) if height == inside => {
+          if let Some(last) = hits.last_mut() {
+            last.exit_distance = (ray.travelled + t_exit) * block;
+          }
+        }
+        // a lower top past a taller wall can still show from above
+        Some(inside) if height < inside => {
+          hit = Some((t, t_exit, along.fract(), face, cell));
+          top_only = true;
+        }
+        _ => {
+          hit = Some((t, t_exit, along.fract(), face, cell));
+          portal = level.portals.through((i as usize, j as usize), face);
+        }
+      }
+      inside_wall = Some(height);
+    } else {
+      // segments have no thickness, so they have no top either
+      if let Some((t_segment, along, face, tile)) = segment_hit(level, i as usize, j as usize, ray.pos, ray.dir, t, t_exit) {
+        hit = Some((t_segment, t_segment, along, face, tile));
+      }
+      inside_wall = None;
     }
 
-    if draw_line {
-      framebuffer.set_pixel(x as u32, y as u32);
+    inside_low_ceiling = is_low_ceiling(cell);
+
+    if let Some((t, t_exit, along, face, impact)) = hit {
+      let distance = (ray.travelled + t) * block;
+      let height = level.wall_height(i as usize, j as usize);
+      // a face from its top down to its top is no face, just the top
+      let bottom = if top_only { height } else if is_low_ceiling(impact) { LOW_CEILING } else { 0.0 };
+      let portal = portal.filter(|_| from_open && ray.crossings < MAX_PORTAL_CROSSINGS);
+      let reflected = portal.is_none() && is_mirror(cell) && from_open && ray.bounces < level.mirror_bounces;
+
+      // flip so textures read left to right on every face
+      let tex_u = match face {
+        Face::East | Face::North => 1.0 - along,
+        Face::West | Face::South => along,
+      };
+
+      // carry on out of the other end of the portal, or from the mirror
+      // back into the cell the ray came from
+      let next = if let Some(portal) = portal {
+        Some(Ray {
+          pos: portal.point(Vector2::new(pos_x + t * dir_x, pos_y + t * dir_y)),
+          dir: portal.direction(ray.dir),
+          cell: portal.exit_cell(),
+          travelled: ray.travelled + t,
+          bounces: ray.bounces,
+          crossings: ray.crossings + 1,
+        })
+      } else if reflected {
+        let (dir, cell) = if face.is_vertical() {
+          (Vector2::new(-dir_x, dir_y), (i - step_i, j))
+        } else {
+          (Vector2::new(dir_x, -dir_y), (i, j - step_j))
+        };
+        Some(Ray {
+          pos: Vector2::new(pos_x + t * dir_x, pos_y + t * dir_y),
+          dir,
+          cell,
+          travelled: ray.travelled + t,
+          bounces: ray.bounces + 1,
+          crossings: ray.crossings,
+        })
+      } else {
+        None
+      };
+
+      hits.push(Intersect {
+        distance,
+        impact,
+        cell: (i as usize, j as usize),
+        hit_x: (pos_x + t * dir_x) * block,
+        hit_y: (pos_y + t * dir_y) * block,
+        face,
+        tex_u,
+        height,
+        bottom,
+        exit_distance: (ray.travelled + t_exit) * block,
+        bounces: ray.bounces,
+        onward: next.as_ref().map(|next| Onward { pos: next.pos * block, dir: next.dir, bounces: next.bounces }),
+      });
+
+      if next.is_some() {
+        return next;
+      }
+
+      if height >= level.max_wall_height && bottom == 0.0 && !is_see_through(impact) {
+        break;
+      }
     }
 
-    prev_i = Some(i);
-    prev_j = Some(j);
-    d += 10.0;
+    // step into the next cell
+    if side_x < side_y {
+      t = side_x;
+      side_x += delta_x;
+      i += step_i;
+      face = if step_i > 0 { Face::West } else { Face::East };
+    } else {
+      t = side_y;
+      side_y += delta_y;
+      j += step_j;
+      face = if step_j > 0 { Face::North } else { Face::South };
+    }
   }
+
+  None
 }
 
+// Segments on a cell edge are reached from both cells; this much slack
+// keeps rounding at the grid line from missing them
+const EDGE_SLACK: f32 = 1e-4;
 
+// Nearest thin or diagonal wall of cell (i, j) that the ray crosses
+// between `t_min` and `t_max` (in cells): its distance, position along
+// it, face and tile. The face is the grid face its normal is closest to,
+// so segments shade and texture like the walls around them.
+fn segment_hit(
+  level: &Level,
+  i: usize,
+  j: usize,
+  pos: Vector2,
+  dir: Vector2,
+  t_min: f32,
+  t_max: f32,
+) -> Option<(f32, f32, Face, char)> {
+  let mut nearest: Option<(f32, f32, Face, char)> = None;
 
+  for segment in level.segments_at(i, j) {
+    let e = segment.to - segment.from;
+    let denom = dir.x * e.y - dir.y * e.x;
+    if denom.abs() < f32::EPSILON {
+      continue; // parallel to the ray
+    }
+    let w = segment.from - pos;
+    let t = (w.x * e.y - w.y * e.x) / denom;
+    let s = (w.x * dir.y - w.y * dir.x) / denom;
+    if t < t_min - EDGE_SLACK || t > t_max + EDGE_SLACK || !(0.0..=1.0).contains(&s) {
+      continue;
+    }
+    if nearest.is_some_and(|(best, ..)| t >= best) {
+      continue;
+    }
+
+    // normal on the side the ray comes from
+    let (mut nx, mut ny) = (-e.y, e.x);
+    if nx * dir.x + ny * dir.y > 0.0 {
+      nx = -nx;
+      ny = -ny;
+    }
+    let face = if nx.abs() >= ny.abs() {
+      if nx < 0.0 { Face::West } else { Face::East }
+    } else if ny < 0.0 {
+      Face::North
+    } else {
+      Face::South
+    };
+
+    // like on grid faces, `along` grows with x on north / south faces
+    // and with y on east / west ones
+    let axis = if face.is_vertical() { e.y } else { e.x };
+    let along = if axis >= 0.0 { s } else { 1.0 - s };
+    nearest = Some((t.max(0.0), along.min(0.9999), face, segment.tile));
+  }
+
+  nearest
+}
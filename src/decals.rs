@@ -0,0 +1,59 @@
+// decals.rs
+
+use std::collections::HashMap;
+
+use crate::caster::Face;
+use crate::gfx::Color;
+use crate::lighting::mix;
+use crate::textures::TextureManager;
+
+// Image stuck on one face of a wall cell: a sign, a splat, a scorch
+// mark. Decals from the `[decals]` section of a level are added when it
+// loads; anything else can add more while playing.
+#[derive(Clone)]
+pub struct Decal {
+  pub texture: String, // decal name in assets/tiles.txt
+  pub cell: (usize, usize),
+  pub face: Face,
+  pub u: f32,      // center across the face, 0.0 left to 1.0 right as seen
+  pub height: f32, // center above the floor, in blocks
+  pub size: f32,   // width and height, in blocks
+}
+
+#[derive(Default)]
+pub struct Decals {
+  faces: HashMap<((usize, usize), Face), Vec<Decal>>,
+}
+
+impl Decals {
+  pub fn add(&mut self, decal: Decal) {
+    self.faces.entry((decal.cell, decal.face)).or_default().push(decal);
+  }
+
+  // Decals on one face, oldest first
+  pub fn on(&self, cell: (usize, usize), face: Face) -> &[Decal] {
+    self.faces.get(&(cell, face)).map_or(&[], |decals| decals.as_slice())
+  }
+
+  // Blend the decals in `decals` over `color`, the wall texel at `u`
+  // across the face and `z` blocks above the floor. Later decals go on
+  // top; ones with no texture in the manifest are skipped, as decals
+  // added while playing are never checked. `screen_size` is the
+  // on-screen size of one block.
+  pub fn apply(decals: &[Decal], texture_cache: &TextureManager, color: Color, u: f32, z: f32, screen_size: f32) -> Color {
+    let mut color = color;
+    for decal in decals {
+      let du = (u - decal.u) / decal.size + 0.5;
+      let dv = (decal.height - z) / decal.size + 0.5;
+      if !(0.0..1.0).contains(&du) || !(0.0..1.0).contains(&dv) {
+        continue;
+      }
+      let Some(texture) = texture_cache.decal(&decal.texture) else {
+        continue;
+      };
+      let texel = texture.sample(du, dv, screen_size * decal.size);
+      color = mix(color, texel, texel.a as f32 / 255.0);
+    }
+    color
+  }
+}
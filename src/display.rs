@@ -0,0 +1,58 @@
+// display.rs
+//
+// Raylib presentation backend: shows frames rendered to a Framebuffer in
+// the window. Nothing else in the renderer needs a window.
+
+use raylib::prelude::*;
+
+use crate::framebuffer::Framebuffer;
+
+#[derive(Default)]
+pub struct Display {
+    texture: Option<Texture2D>, // on the GPU, created on the first present and updated in place
+}
+
+impl Display {
+    // Upload the frame and start drawing the window with it. Everything
+    // else on screen this frame (minimap, HUD) is drawn with the returned
+    // handle, and the frame ends when it is dropped.
+    pub fn swap_buffers<'a>(
+        &mut self,
+        framebuffer: &Framebuffer,
+        window: &'a mut RaylibHandle,
+        raylib_thread: &RaylibThread,
+    ) -> RaylibDrawHandle<'a> {
+        match self.texture.as_mut() {
+            Some(texture) => {
+                if let Err(err) = texture.update_texture(framebuffer.pixels()) {
+                    eprintln!("Could not update the framebuffer texture: {err}");
+                }
+            }
+            None => {
+                let image = to_image(framebuffer);
+                match window.load_texture_from_image(raylib_thread, &image) {
+                    Ok(texture) => self.texture = Some(texture),
+                    Err(err) => eprintln!("Could not create the framebuffer texture: {err}"),
+                }
+            }
+        }
+
+        let mut renderer = window.begin_drawing(raylib_thread);
+        if let Some(texture) = &self.texture {
+            renderer.draw_texture(texture, 0, 0, Color::WHITE);
+        }
+        renderer
+    }
+}
+
+// Copy of the frame as an R8G8B8A8 raylib image
+fn to_image(framebuffer: &Framebuffer) -> Image {
+    let image = Image::gen_image_color(framebuffer.width as i32, framebuffer.height as i32, Color::BLACK);
+    let pixels = framebuffer.pixels();
+    // SAFETY: the image was generated as width x height R8G8B8A8, exactly
+    // the size and layout of the framebuffer's pixels
+    unsafe {
+        std::ptr::copy_nonoverlapping(pixels.as_ptr(), image.data as *mut u8, pixels.len());
+    }
+    image
+}
@@ -0,0 +1,114 @@
+// doors.rs
+
+use std::collections::HashMap;
+
+use crate::maze::{Maze, is_wall};
+
+const OPEN_SPEED: f32 = 1.5;   // fraction of the door per second
+const STAY_OPEN: f32 = 4.0;    // seconds before an open door closes again
+const PASSABLE_AT: f32 = 0.9;  // how open a door must be to walk through
+
+// 'D' is a door, 'L' a door that only opens while the player carries a key
+pub fn is_door(cell: char) -> bool {
+    cell == 'D' || cell == 'L'
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Motion {
+    Closed,
+    Opening,
+    Open,
+    Closing,
+}
+
+pub struct Door {
+    pub open: f32,        // 0.0 closed, 1.0 fully slid into the wall
+    pub locked: bool,     // needs a key
+    pub horizontal: bool, // panel runs along x, through the middle of the cell
+    motion: Motion,
+    timer: f32,
+}
+
+pub struct Doors {
+    doors: HashMap<(usize, usize), Door>,
+}
+
+impl Doors {
+    pub fn from_maze(maze: &Maze) -> Self {
+        let mut doors = HashMap::new();
+        for (j, row) in maze.iter().enumerate() {
+            for (i, &cell) in row.iter().enumerate() {
+                if !is_door(cell) {
+                    continue;
+                }
+                // a door between walls on its left and right runs along x
+                let left = i > 0 && is_wall(row[i - 1]);
+                let right = row.get(i + 1).is_some_and(|&c| is_wall(c));
+                doors.insert((i, j), Door {
+                    open: 0.0,
+                    locked: cell == 'L',
+                    horizontal: left && right,
+                    motion: Motion::Closed,
+                    timer: 0.0,
+                });
+            }
+        }
+        Doors { doors }
+    }
+
+    pub fn get(&self, i: usize, j: usize) -> Option<&Door> {
+        self.doors.get(&(i, j))
+    }
+
+    pub fn is_passable(&self, i: usize, j: usize) -> bool {
+        self.get(i, j).is_some_and(|door| door.open >= PASSABLE_AT)
+    }
+
+    // Start opening the door at (i, j). Returns false if there is no door
+    // there or it is locked and the player has no key.
+    pub fn use_door(&mut self, i: usize, j: usize, has_key: bool) -> bool {
+        let Some(door) = self.doors.get_mut(&(i, j)) else {
+            return false;
+        };
+        if door.locked && !has_key {
+            return false;
+        }
+        if door.motion != Motion::Open {
+            door.motion = Motion::Opening;
+        }
+        true
+    }
+
+    // Slide doors and close them again after a while. A door never closes
+    // on the player standing in it.
+    pub fn update(&mut self, dt: f32, player_cell: (usize, usize)) {
+        for (&cell, door) in self.doors.iter_mut() {
+            match door.motion {
+                Motion::Closed => {}
+                Motion::Opening => {
+                    door.open = (door.open + OPEN_SPEED * dt).min(1.0);
+                    if door.open >= 1.0 {
+                        door.motion = Motion::Open;
+                        door.timer = 0.0;
+                    }
+                }
+                Motion::Open => {
+                    door.timer += dt;
+                    if door.timer >= STAY_OPEN && cell != player_cell {
+                        door.motion = Motion::Closing;
+                    }
+                }
+                Motion::Closing => {
+                    if cell == player_cell {
+                        door.motion = Motion::Opening;
+                        continue;
+                    }
+                    door.open = (door.open - OPEN_SPEED * dt).max(0.0);
+                    if door.open <= 0.0 {
+                        door.motion = Motion::Closed;
+                    }
+                }
+            }
+        }
+    }
+}
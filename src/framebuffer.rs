@@ -1,35 +1,84 @@
 // framebuffer.rs
+//
+// Frames are drawn to memory; display.rs puts them in a window. They can
+// also be saved as PPM, BMP or PNG, encoded here without raylib.
 
-use raylib::prelude::*;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::gfx::Color;
 
 pub struct Framebuffer {
     pub width: u32,
     pub height: u32,
-    pub color_buffer: Image,
+    pixels: Vec<u8>, // RGBA, 4 bytes per pixel, row by row from the top
     background_color: Color,
     current_color: Color,
 }
 
 impl Framebuffer {
     pub fn new(width: u32, height: u32) -> Self {
-        let color_buffer = Image::gen_image_color(width as i32, height as i32, Color::BLACK);
-        Framebuffer {
+        let mut framebuffer = Framebuffer {
             width,
             height,
-            color_buffer,
+            pixels: vec![0; width as usize * height as usize * 4],
             background_color: Color::BLACK,
             current_color: Color::WHITE,
-        }
+        };
+        framebuffer.clear();
+        framebuffer
     }
 
+    // Fill with the background color, reusing the buffer
     pub fn clear(&mut self) {
-        self.color_buffer = Image::gen_image_color(self.width as i32, self.height as i32, self.background_color);
+        let background = [
+            self.background_color.r,
+            self.background_color.g,
+            self.background_color.b,
+            self.background_color.a,
+        ];
+        for pixel in self.pixels.chunks_exact_mut(4) {
+            pixel.copy_from_slice(&background);
+        }
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y as usize * self.width as usize + x as usize) * 4
     }
 
     pub fn set_pixel(&mut self, x: u32, y: u32) {
         if x < self.width && y < self.height {
-            self.color_buffer.draw_pixel(x as i32, y as i32, self.current_color);
+            let index = self.index(x, y);
+            let color = self.current_color;
+            self.pixels[index..index + 4].copy_from_slice(&[color.r, color.g, color.b, color.a]);
+        }
+    }
+
+    // Like set_pixel, but mixes the current color over what is already
+    // there by its alpha
+    pub fn blend_pixel(&mut self, x: u32, y: u32) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let src = self.current_color;
+        if src.a == 255 {
+            self.set_pixel(x, y);
+            return;
         }
+        let index = self.index(x, y);
+        let alpha = src.a as f32 / 255.0;
+        let mix = |s: u8, d: u8| (s as f32 * alpha + d as f32 * (1.0 - alpha)) as u8;
+        let dst = &mut self.pixels[index..index + 4];
+        dst[0] = mix(src.r, dst[0]);
+        dst[1] = mix(src.g, dst[1]);
+        dst[2] = mix(src.b, dst[2]);
+        dst[3] = 255;
+    }
+
+    // The whole frame as RGBA bytes
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
     }
 
     pub fn set_background_color(&mut self, color: Color) {
@@ -40,19 +89,292 @@ impl Framebuffer {
         self.current_color = color;
     }
 
-    pub fn _render_to_file(&self, file_path: &str) {
-        self.color_buffer.export_image(file_path);
+    // Save the frame, in the format its extension names
+    pub fn render_to_file(&self, path: &Path) -> io::Result<()> {
+        let format = ImageFormat::from_path(path).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a .ppm, .bmp or .png file", path.display()))
+        })?;
+        fs::write(path, self.encode(format))
+    }
+
+    pub fn encode(&self, format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Ppm => encode_ppm(self.width, self.height, &self.pixels),
+            ImageFormat::Bmp => encode_bmp(self.width, self.height, &self.pixels),
+            ImageFormat::Png => encode_png(self.width, self.height, &self.pixels),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ImageFormat {
+    Ppm,
+    Bmp,
+    Png,
+}
+
+impl ImageFormat {
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(ImageFormat::Ppm),
+            "bmp" => Some(ImageFormat::Bmp),
+            "png" => Some(ImageFormat::Png),
+            _ => None,
+        }
+    }
+}
+
+// The encoders take RGBA pixels row by row from the top, like the
+// framebuffer holds them
+
+// Binary PPM (P6); drops alpha
+pub fn encode_ppm(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.reserve(rgba.len() / 4 * 3);
+    for pixel in rgba.chunks_exact(4) {
+        data.extend_from_slice(&pixel[..3]);
     }
+    data
+}
+
+// Uncompressed 24-bit BMP; drops alpha. Rows are stored bottom up, BGR,
+// each padded to a multiple of 4 bytes.
+pub fn encode_bmp(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    const HEADER_SIZE: u32 = 14 + 40;
+    let row_size = (width * 3).div_ceil(4) * 4;
+    let image_size = row_size * height;
+
+    let mut data = Vec::with_capacity((HEADER_SIZE + image_size) as usize);
+    // file header
+    data.extend_from_slice(b"BM");
+    data.extend_from_slice(&(HEADER_SIZE + image_size).to_le_bytes());
+    data.extend_from_slice(&[0; 4]);
+    data.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+    // BITMAPINFOHEADER
+    data.extend_from_slice(&40u32.to_le_bytes());
+    data.extend_from_slice(&(width as i32).to_le_bytes());
+    data.extend_from_slice(&(height as i32).to_le_bytes());
+    data.extend_from_slice(&1u16.to_le_bytes()); // planes
+    data.extend_from_slice(&24u16.to_le_bytes()); // bits per pixel
+    data.extend_from_slice(&0u32.to_le_bytes()); // no compression
+    data.extend_from_slice(&image_size.to_le_bytes());
+    data.extend_from_slice(&2835i32.to_le_bytes()); // 72 dpi
+    data.extend_from_slice(&2835i32.to_le_bytes());
+    data.extend_from_slice(&[0; 8]); // palette sizes
 
-    pub fn swap_buffers(
-        &self,
-        window: &mut RaylibHandle,
-        raylib_thread: &RaylibThread,
-    ) {
-        if let Ok(texture) = window.load_texture_from_image(raylib_thread, &self.color_buffer) {
-            let mut renderer = window.begin_drawing(raylib_thread);
-            renderer.draw_texture(&texture, 0, 0, Color::WHITE);
-            // HUD and overlays will be drawn by caller after swap via window drawing API
+    let padding = (row_size - width * 3) as usize;
+    for row in rgba.chunks_exact(width as usize * 4).rev() {
+        for pixel in row.chunks_exact(4) {
+            data.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
         }
+        data.extend(std::iter::repeat_n(0, padding));
+    }
+    data
+}
+
+// 8-bit RGBA PNG. The image data is zlib with stored (uncompressed)
+// deflate blocks, which every decoder reads and costs nothing to write.
+pub fn encode_png(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    // every row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity(rgba.len() + height as usize);
+    for row in rgba.chunks_exact(width as usize * 4) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // bit depth 8, color type 6 (RGBA), deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_chunk(&mut data, b"IHDR", &header);
+    png_chunk(&mut data, b"IDAT", &zlib_store(&raw));
+    png_chunk(&mut data, b"IEND", &[]);
+    data
+}
+
+fn png_chunk(data: &mut Vec<u8>, kind: &[u8; 4], body: &[u8]) {
+    data.extend_from_slice(&(body.len() as u32).to_be_bytes());
+    let start = data.len();
+    data.extend_from_slice(kind);
+    data.extend_from_slice(body);
+    let crc = crc32(&data[start..]);
+    data.extend_from_slice(&crc.to_be_bytes());
+}
+
+// zlib stream of stored deflate blocks, at most 65535 bytes each
+fn zlib_store(bytes: &[u8]) -> Vec<u8> {
+    const BLOCK: usize = 65535;
+    let mut data = Vec::with_capacity(bytes.len() + bytes.len() / BLOCK * 5 + 11);
+    data.extend_from_slice(&[0x78, 0x01]); // deflate, 32K window, no dictionary
+    let blocks = bytes.chunks(BLOCK).collect::<Vec<_>>();
+    if blocks.is_empty() {
+        data.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    for (i, block) in blocks.iter().enumerate() {
+        let last = (i == blocks.len() - 1) as u8;
+        let len = block.len() as u16;
+        data.push(last);
+        data.extend_from_slice(&len.to_le_bytes());
+        data.extend_from_slice(&(!len).to_le_bytes());
+        data.extend_from_slice(block);
+    }
+    data.extend_from_slice(&adler32(bytes).to_be_bytes());
+    data
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // 5552 bytes is the most that can be summed before `b` could overflow
+    for chunk in bytes.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { 0xedb88320 ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xffffffffu32;
+    for &byte in bytes {
+        crc = CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc ^ 0xffffffff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RGBA pixels where every channel varies, so swapped or shifted bytes show
+    fn pattern(width: u32, height: u32) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| [x as u8, y as u8, (x * 7 + y * 13) as u8, (x + y) as u8 | 0x80])
+            .collect()
+    }
+
+    // (kind, body) of every chunk after the signature, checking the CRCs
+    fn png_chunks(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        assert_eq!(&data[..8], b"\x89PNG\r\n\x1a\n");
+        let mut chunks = Vec::new();
+        let mut rest = &data[8..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let kind: [u8; 4] = rest[4..8].try_into().unwrap();
+            let crc = u32::from_be_bytes(rest[8 + len..12 + len].try_into().unwrap());
+            assert_eq!(crc32(&rest[4..8 + len]), crc, "bad CRC on {:?}", kind);
+            chunks.push((kind, &rest[8..8 + len]));
+            rest = &rest[12 + len..];
+        }
+        chunks
+    }
+
+    #[test]
+    fn checksums_match_known_values() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler32(&[]), 1);
+    }
+
+    #[test]
+    fn ppm_has_header_and_rgb() {
+        let data = encode_ppm(2, 1, &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(data, b"P6\n2 1\n255\n\x01\x02\x03\x05\x06\x07");
+    }
+
+    // 3 pixels are 9 bytes, so every row gets 3 bytes of padding
+    #[test]
+    fn bmp_pads_odd_width_rows() {
+        let (width, height) = (3, 2);
+        let rgba = pattern(width, height);
+        let data = encode_bmp(width, height, &rgba);
+
+        assert_eq!(&data[..2], b"BM");
+        assert_eq!(u32::from_le_bytes(data[2..6].try_into().unwrap()), 54 + 2 * 12);
+        assert_eq!(data.len(), 54 + 2 * 12);
+        assert_eq!(i32::from_le_bytes(data[18..22].try_into().unwrap()), 3);
+        assert_eq!(i32::from_le_bytes(data[22..26].try_into().unwrap()), 2);
+        assert_eq!(u32::from_le_bytes(data[34..38].try_into().unwrap()), 2 * 12);
+
+        // bottom row first, BGR
+        for (stored, y) in data[54..].chunks_exact(12).zip([1, 0]) {
+            for x in 0..3 {
+                let p = &rgba[(y * 3 + x) * 4..][..4];
+                assert_eq!(&stored[x * 3..x * 3 + 3], &[p[2], p[1], p[0]]);
+            }
+            assert_eq!(&stored[9..], &[0, 0, 0]);
+        }
+    }
+
+    // 200x100 RGBA is 80100 bytes of filtered rows, so the IDAT needs more
+    // than one stored block
+    #[test]
+    fn png_round_trips_with_several_stored_blocks() {
+        let (width, height) = (200, 100);
+        let rgba = pattern(width, height);
+        let data = encode_png(width, height, &rgba);
+
+        let mut reader = png::Decoder::new(data.as_slice()).read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((frame.width, frame.height), (width, height));
+        assert_eq!(frame.color_type, png::ColorType::Rgba);
+        assert_eq!(&decoded[..frame.buffer_size()], rgba.as_slice());
+
+        let chunks = png_chunks(&data);
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+
+        // walk the stored blocks by hand; decoders may skip the Adler-32
+        let zlib = chunks[1].1;
+        assert_eq!(&zlib[..2], &[0x78, 0x01]);
+        let mut rest = &zlib[2..];
+        let mut raw = Vec::new();
+        let mut blocks = 0;
+        loop {
+            let last = rest[0];
+            let len = u16::from_le_bytes([rest[1], rest[2]]);
+            let nlen = u16::from_le_bytes([rest[3], rest[4]]);
+            assert_eq!(nlen, !len);
+            raw.extend_from_slice(&rest[5..5 + len as usize]);
+            rest = &rest[5 + len as usize..];
+            blocks += 1;
+            if last == 1 {
+                break;
+            }
+            assert_eq!((last, len), (0, 65535), "only the last block may be short");
+        }
+        assert_eq!(blocks, 2);
+        assert_eq!(rest, adler32(&raw).to_be_bytes());
+
+        let expected: Vec<u8> = rgba
+            .chunks_exact(width as usize * 4)
+            .flat_map(|row| std::iter::once(0).chain(row.iter().copied()))
+            .collect();
+        assert_eq!(raw, expected);
     }
 }
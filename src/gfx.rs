@@ -0,0 +1,74 @@
+// gfx.rs
+//
+// Plain color and vector types the renderer works in, so frames can be
+// drawn without a window; display.rs is what puts them on screen.
+
+use std::ops::{Add, Mul, Sub};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Color {
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Color { r, g, b, a }
+    }
+
+    // The named colors the game uses, with raylib's values
+    pub const BLACK: Color = Color::new(0, 0, 0, 255);
+    pub const WHITE: Color = Color::new(255, 255, 255, 255);
+    pub const WHITESMOKE: Color = Color::new(245, 245, 245, 255);
+    pub const LIGHTGRAY: Color = Color::new(211, 211, 211, 255);
+    pub const GRAY: Color = Color::new(128, 128, 128, 255);
+    pub const DARKGRAY: Color = Color::new(169, 169, 169, 255);
+    pub const YELLOW: Color = Color::new(255, 255, 0, 255);
+    pub const GOLD: Color = Color::new(255, 215, 0, 255);
+    pub const MAROON: Color = Color::new(128, 0, 0, 255);
+    pub const GREEN: Color = Color::new(0, 128, 0, 255);
+    pub const SKYBLUE: Color = Color::new(135, 206, 235, 255);
+    pub const PURPLE: Color = Color::new(128, 0, 128, 255);
+    pub const VIOLET: Color = Color::new(238, 130, 238, 255);
+    pub const BLUEVIOLET: Color = Color::new(138, 43, 226, 255);
+    pub const BROWN: Color = Color::new(165, 42, 42, 255);
+    pub const DARKBROWN: Color = Color::new(76, 63, 47, 255);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vector2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vector2 {
+    pub const fn new(x: f32, y: f32) -> Self {
+        Vector2 { x, y }
+    }
+}
+
+impl Add for Vector2 {
+    type Output = Vector2;
+
+    fn add(self, other: Vector2) -> Vector2 {
+        Vector2::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Vector2 {
+    type Output = Vector2;
+
+    fn sub(self, other: Vector2) -> Vector2 {
+        Vector2::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Mul<f32> for Vector2 {
+    type Output = Vector2;
+
+    fn mul(self, scale: f32) -> Vector2 {
+        Vector2::new(self.x * scale, self.y * scale)
+    }
+}
@@ -0,0 +1,240 @@
+// golden.rs
+//
+// Golden-image tests for the 3D renderer. Every test renders a fixed view
+// headless and compares it with tests/golden/<name>.ppm. Channels may be
+// off by TOLERANCE, and a few pixels may differ outright, so rounding
+// differences between machines don't fail the suite.
+//
+// Run them with `cargo test --no-default-features`. The default `window`
+// feature builds raylib, which needs cmake; the renderer doesn't.
+//
+// A failing test writes <name>.actual.ppm and <name>.diff.ppm next to the
+// reference; the diff shows matching pixels dimmed and the rest in red.
+// After an intended change to the picture, run with UPDATE_GOLDEN=1 to
+// rewrite the references, and look at them before committing.
+
+use std::fs;
+use std::path::Path;
+
+use crate::caster::Face;
+use crate::decals::Decal;
+use crate::framebuffer::encode_ppm;
+use crate::gfx::Vector2;
+use crate::headless::render_frame;
+use crate::player::{CROUCH_EYE, Player};
+use crate::scene::Scene;
+
+const GOLDEN_DIR: &str = "tests/golden";
+
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 200;
+const BLOCK_SIZE: usize = 100;
+// fixed time, so light flicker and sprite animation match every run
+const TIME: f32 = 1.0;
+
+const TOLERANCE: u8 = 8;
+// fraction of the pixels allowed to be off by more than TOLERANCE
+const MAX_MISMATCHED: f32 = 0.002;
+
+// Standing in the middle of cell (x, y), facing `degrees` (0 is east,
+// 90 south)
+fn player_at(x: f32, y: f32, degrees: f32) -> Player {
+    let block = BLOCK_SIZE as f32;
+    Player::new(Vector2::new(x * block, y * block), degrees.to_radians())
+}
+
+fn check(name: &str, level: &str, player: &Player) {
+    check_scene(name, Scene::load(level, BLOCK_SIZE), player);
+}
+
+fn check_scene(name: &str, mut scene: Scene, player: &Player) {
+    let frame = render_frame(&mut scene, player, WIDTH, HEIGHT, TIME);
+
+    let dir = Path::new(GOLDEN_DIR);
+    let reference_path = dir.join(format!("{name}.ppm"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::create_dir_all(dir).unwrap();
+        write(&reference_path, encode_ppm(WIDTH, HEIGHT, frame.pixels()));
+        return;
+    }
+
+    let (width, height, reference) = read_ppm(&reference_path).unwrap_or_else(|| {
+        panic!("No reference image {}; run with UPDATE_GOLDEN=1 to create it", reference_path.display())
+    });
+    assert_eq!((width, height), (WIDTH, HEIGHT), "{}: reference has a different size", name);
+
+    // alpha isn't part of the picture, only RGB is compared
+    let mut diff = Vec::with_capacity(frame.pixels().len());
+    let mut mismatched = 0;
+    for (a, r) in frame.pixels().chunks_exact(4).zip(reference.chunks_exact(3)) {
+        if a.iter().zip(r).all(|(a, r)| a.abs_diff(*r) <= TOLERANCE) {
+            diff.extend([r[0] / 4, r[1] / 4, r[2] / 4, 255]);
+        } else {
+            mismatched += 1;
+            diff.extend([255, 0, 0, 255]);
+        }
+    }
+
+    let allowed = ((WIDTH * HEIGHT) as f32 * MAX_MISMATCHED) as usize;
+    if mismatched > allowed {
+        let actual_path = dir.join(format!("{name}.actual.ppm"));
+        let diff_path = dir.join(format!("{name}.diff.ppm"));
+        write(&actual_path, encode_ppm(WIDTH, HEIGHT, frame.pixels()));
+        write(&diff_path, encode_ppm(WIDTH, HEIGHT, &diff));
+        panic!(
+            "{}: {} pixels differ from the reference (at most {} may), see {} and {}",
+            name,
+            mismatched,
+            allowed,
+            actual_path.display(),
+            diff_path.display(),
+        );
+    }
+}
+
+fn write(path: &Path, data: Vec<u8>) {
+    fs::write(path, data).unwrap_or_else(|err| panic!("Failed to write {}: {}", path.display(), err));
+}
+
+// Width, height and RGB bytes of a binary PPM as encode_ppm writes them
+fn read_ppm(path: &Path) -> Option<(u32, u32, Vec<u8>)> {
+    let data = fs::read(path).ok()?;
+    // the header is four whitespace-separated fields: P6, width, height, 255
+    let mut fields = Vec::new();
+    let mut start = None;
+    let mut end = 0;
+    for (i, &byte) in data.iter().enumerate() {
+        match (byte.is_ascii_whitespace(), start) {
+            (false, None) => start = Some(i),
+            (true, Some(s)) => {
+                fields.push(std::str::from_utf8(&data[s..i]).ok()?);
+                start = None;
+                if fields.len() == 4 {
+                    end = i + 1;
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let [magic, width, height, max] = fields.as_slice() else { return None };
+    if *magic != "P6" || *max != "255" {
+        return None;
+    }
+    let (width, height): (u32, u32) = (width.parse().ok()?, height.parse().ok()?);
+    let pixels = data[end..].to_vec();
+    (pixels.len() == (width * height * 3) as usize).then_some((width, height, pixels))
+}
+
+#[test]
+fn maze_start() {
+    check("maze_start", "maze.txt", &player_at(1.5, 1.5, 60.0));
+}
+
+// the portal at the east end of the top corridor shows the far side
+#[test]
+fn maze_portal() {
+    check("maze_portal", "maze.txt", &player_at(2.5, 1.5, 0.0));
+}
+
+#[test]
+fn maze_mirror() {
+    check("maze_mirror", "maze.txt", &player_at(7.5, 5.5, 0.0));
+}
+
+// looking up at the sky over the outdoor cells
+#[test]
+fn maze_sky() {
+    let mut player = player_at(5.5, 3.5, 180.0);
+    player.pitch = 0.3;
+    check("maze_sky", "maze.txt", &player);
+}
+
+#[test]
+fn maze_crouching_at_low_ceiling() {
+    let mut player = player_at(1.5, 3.5, 90.0);
+    player.stance = CROUCH_EYE;
+    check("maze_crouching_at_low_ceiling", "maze.txt", &player);
+}
+
+// eye above the half walls next to it, looking down on their tops
+#[test]
+fn maze_jumping_by_half_wall() {
+    let mut player = player_at(6.5, 3.8, 90.0);
+    player.jump = 0.25;
+    check("maze_jumping_by_half_wall", "maze.txt", &player);
+}
+
+// a run of low ceiling cells is one block: a face where it starts and
+// one underside
+#[test]
+fn low_ceiling_run() {
+    let mut player = player_at(1.5, 2.5, 0.0);
+    player.stance = CROUCH_EYE;
+    check("low_ceiling_run", "tests/fixtures/low_ceiling.txt", &player);
+}
+
+// crouched under the run, its underside overhead
+#[test]
+fn low_ceiling_inside() {
+    let mut player = player_at(3.5, 2.5, 20.0);
+    player.stance = CROUCH_EYE;
+    player.pitch = 0.2;
+    check("low_ceiling_inside", "tests/fixtures/low_ceiling.txt", &player);
+}
+
+// jumping above a row of half walls, whose tops join into one
+#[test]
+fn half_wall_row() {
+    let mut player = player_at(1.5, 2.5, 25.0);
+    player.jump = 0.45;
+    player.pitch = -0.4;
+    check("half_wall_row", "tests/fixtures/half_walls.txt", &player);
+}
+
+// an arrow chalked while playing, next to a decal the manifest doesn't
+// have, which is left out
+#[test]
+fn room_chalked_arrow() {
+    let mut scene = Scene::load("tests/fixtures/room.txt", BLOCK_SIZE);
+    let player = player_at(1.5, 1.5, 300.0);
+    crate::chalk_arrow(&mut scene, &player);
+    scene.decals.add(Decal {
+        texture: "no such decal".to_string(),
+        cell: (2, 0),
+        face: Face::South,
+        u: 0.5,
+        height: 0.5,
+        size: 0.5,
+    });
+    check_scene("room_chalked_arrow", scene, &player);
+}
+
+// the floor, sky and light of the room at the far end of a portal in
+// an outer wall, past which there is nothing
+#[test]
+fn portal_destination() {
+    let mut player = player_at(3.5, 2.8, 270.0);
+    player.pitch = 0.1;
+    check("portal_destination", "tests/fixtures/portal.txt", &player);
+}
+
+// pillars of different heights, a point light and a sprite
+#[test]
+fn room() {
+    check("room", "tests/fixtures/room.txt", &player_at(1.5, 4.5, 340.0));
+}
+
+// pale linear fog down a long room
+#[test]
+fn linear_fog() {
+    check("linear_fog", "tests/fixtures/fog.txt", &player_at(1.5, 1.5, 10.0));
+}
+
+// both diagonals and the thin wall, none of them edge-on
+#[test]
+fn thin_walls() {
+    check("thin_walls", "tests/fixtures/thin_walls.txt", &player_at(5.5, 3.8, 225.0));
+}
+
+
@@ -0,0 +1,20 @@
+// headless.rs
+//
+// Renders frames to memory with no window, for machines without a
+// display. Scenes load the way the game loads them, so the frames show
+// what the game shows.
+
+use crate::framebuffer::Framebuffer;
+use crate::player::Player;
+use crate::renderer::BACKGROUND_COLOR;
+use crate::scene::Scene;
+
+// The 3D view from `player` at `time` seconds into the game
+pub fn render_frame(scene: &mut Scene, player: &Player, width: u32, height: u32, time: f32) -> Framebuffer {
+    let mut framebuffer = Framebuffer::new(width, height);
+    framebuffer.set_background_color(BACKGROUND_COLOR);
+    framebuffer.clear();
+    scene.lighting.update(time);
+    scene.render(&mut framebuffer, player, time);
+    framebuffer
+}
@@ -0,0 +1,223 @@
+// lighting.rs
+
+use crate::caster::Face;
+use crate::doors::is_door;
+use crate::gfx::{Color, Vector2};
+use crate::maze::{Level, Maze, is_low_ceiling, is_see_through, is_wall};
+
+#[derive(Clone, Copy)]
+pub enum Fog {
+  None,
+  // no fog before `start`, fully fogged at `end` (world units)
+  Linear { start: f32, end: f32 },
+  // 1 - e^(-density * distance)
+  Exponential { density: f32 },
+}
+
+// Light source declared in the `[lights]` section of a level. Position
+// and radius are in cells.
+#[derive(Clone, Copy)]
+pub struct PointLight {
+  pub pos: Vector2,
+  pub color: Color,
+  pub radius: f32,
+  pub flicker: f32, // 0.0 is steady, 1.0 can dip to black
+}
+
+// Per-channel brightness multiplier
+pub type Rgb = [f32; 3];
+
+// A level light in world units, with the cells it can reach
+struct PlacedLight {
+  pos: Vector2,
+  color: Rgb,
+  radius: f32,
+  flicker: f32,
+  visible: Vec<Vec<bool>>, // [row][col]: cell center has line of sight to the light
+  intensity: f32,          // current flicker value, refreshed by `update`
+}
+
+// Lighting stage applied to every sample the 3D view draws (walls,
+// floor, ceiling and sprites), so everything at the same depth fades
+// into the fog the same way.
+pub struct Lighting {
+  pub fog: Fog,
+  pub fog_color: Color,
+  pub north_south_shade: f32, // brightness multiplier for N/S wall faces
+  pub east_west_shade: f32,   // brightness multiplier for E/W wall faces
+  pub ambient: f32,           // brightness of cells no light reaches
+  lights: Vec<PlacedLight>,
+  block_size: f32,
+}
+
+impl Default for Lighting {
+  fn default() -> Self {
+    Lighting {
+      fog: Fog::Exponential { density: 0.0015 },
+      fog_color: Color::new(10, 10, 20, 255),
+      north_south_shade: 1.0,
+      east_west_shade: 0.7,
+      ambient: 1.0,
+      lights: Vec::new(),
+      block_size: 1.0,
+    }
+  }
+}
+
+impl Lighting {
+  // Place the level's lights and fog in the world. Shadows are per cell: a
+  // light only reaches cells whose centers it can see, so walls keep light
+  // inside rooms. Lights never move, so this only runs when a level is loaded.
+  pub fn set_lights(&mut self, level: &Level, block_size: usize) {
+    let block = block_size as f32;
+    self.block_size = block;
+    self.ambient = level.ambient;
+    self.fog = match level.fog {
+      Fog::None => Fog::None,
+      Fog::Linear { start, end } => Fog::Linear { start: start * block, end: end * block },
+      Fog::Exponential { density } => Fog::Exponential { density: density / block },
+    };
+    self.fog_color = level.fog_color;
+    self.lights = level
+      .lights
+      .iter()
+      .map(|light| {
+        let pos = Vector2::new(light.pos.x * block, light.pos.y * block);
+        let radius = light.radius * block;
+        let visible = level
+          .maze
+          .iter()
+          .enumerate()
+          .map(|(j, row)| {
+            (0..row.len())
+              .map(|i| {
+                let center = Vector2::new((i as f32 + 0.5) * block, (j as f32 + 0.5) * block);
+                // door panels, see-through walls and low ceilings are lit from the cells around them
+                (!is_wall(row[i]) || is_door(row[i]) || is_see_through(row[i]) || is_low_ceiling(row[i])) && line_of_sight(&level.maze, pos, center, block, radius)
+              })
+              .collect()
+          })
+          .collect();
+
+        PlacedLight {
+          pos,
+          color: [light.color.r as f32 / 255.0, light.color.g as f32 / 255.0, light.color.b as f32 / 255.0],
+          radius,
+          flicker: light.flicker.clamp(0.0, 1.0),
+          visible,
+          intensity: 1.0,
+        }
+      })
+      .collect();
+  }
+
+  // Advance light flicker to `time` seconds
+  pub fn update(&mut self, time: f32) {
+    for (n, light) in self.lights.iter_mut().enumerate() {
+      // two detuned sines per light read as an irregular flame
+      let seed = n as f32 * 1.7;
+      let wobble = ((time * 11.0 + seed).sin() * (time * 7.3 + seed * 3.1).sin() + 1.0) / 2.0;
+      light.intensity = 1.0 - light.flicker * wobble;
+    }
+  }
+
+  // Light reaching `point`, which lies in (or in front of the wall of) `cell`
+  pub fn illumination(&self, point: Vector2, cell: (usize, usize)) -> Rgb {
+    let mut rgb = [self.ambient; 3];
+    let (i, j) = cell;
+
+    for light in &self.lights {
+      let visible = light.visible.get(j).and_then(|row| row.get(i)).copied().unwrap_or(false);
+      if !visible {
+        continue;
+      }
+      let dx = point.x - light.pos.x;
+      let dy = point.y - light.pos.y;
+      let distance = (dx * dx + dy * dy).sqrt();
+      if distance >= light.radius {
+        continue;
+      }
+      let falloff = 1.0 - distance / light.radius;
+      let strength = falloff * falloff * light.intensity;
+      for (channel, color) in rgb.iter_mut().zip(light.color) {
+        *channel += color * strength;
+      }
+    }
+
+    rgb
+  }
+
+  // Same as `illumination` for a world point, finding its cell
+  pub fn illumination_at(&self, point: Vector2) -> Rgb {
+    if point.x < 0.0 || point.y < 0.0 {
+      return [self.ambient; 3];
+    }
+    let cell = ((point.x / self.block_size) as usize, (point.y / self.block_size) as usize);
+    self.illumination(point, cell)
+  }
+
+  // 0.0 is no fog, 1.0 is only fog
+  pub fn fog_factor(&self, distance: f32) -> f32 {
+    let factor = match self.fog {
+      Fog::None => 0.0,
+      Fog::Linear { start, end } => (distance - start) / (end - start).max(f32::EPSILON),
+      Fog::Exponential { density } => 1.0 - (-density * distance).exp(),
+    };
+    factor.clamp(0.0, 1.0)
+  }
+
+  pub fn face_shade(&self, face: Face) -> f32 {
+    if face.is_vertical() { self.east_west_shade } else { self.north_south_shade }
+  }
+
+  // Light `color` by `light` scaled by `shade`, then blend it towards the
+  // fog color by distance
+  pub fn apply(&self, color: Color, distance: f32, shade: f32, light: Rgb) -> Color {
+    let fog = self.fog_factor(distance);
+    let mix = |c: u8, l: f32, f: u8| (c as f32 * l * shade * (1.0 - fog) + f as f32 * fog).clamp(0.0, 255.0) as u8;
+    Color::new(
+      mix(color.r, light[0], self.fog_color.r),
+      mix(color.g, light[1], self.fog_color.g),
+      mix(color.b, light[2], self.fog_color.b),
+      color.a,
+    )
+  }
+}
+
+// Blend `color` towards `other` by `amount` (0.0 keeps `color`), keeping
+// the alpha of `color`
+pub fn mix(color: Color, other: Color, amount: f32) -> Color {
+  let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * amount).clamp(0.0, 255.0) as u8;
+  Color::new(channel(color.r, other.r), channel(color.g, other.g), channel(color.b, other.b), color.a)
+}
+
+// Walk from `from` to `to` in quarter-cell steps, failing on the first
+// opaque wall before the cell that contains `to`
+fn line_of_sight(maze: &Maze, from: Vector2, to: Vector2, block: f32, max_distance: f32) -> bool {
+  let dx = to.x - from.x;
+  let dy = to.y - from.y;
+  let distance = (dx * dx + dy * dy).sqrt();
+  if distance > max_distance + block {
+    return false;
+  }
+
+  let target = ((to.x / block) as usize, (to.y / block) as usize);
+  let steps = (distance / (block / 4.0)).ceil().max(1.0) as usize;
+  for step in 0..=steps {
+    let t = step as f32 / steps as f32;
+    let x = from.x + dx * t;
+    let y = from.y + dy * t;
+    if x < 0.0 || y < 0.0 {
+      return false;
+    }
+    let (i, j) = ((x / block) as usize, (y / block) as usize);
+    if (i, j) == target {
+      break;
+    }
+    let cell = maze.get(j).and_then(|row| row.get(i));
+    if cell.is_none_or(|&c| is_wall(c) && !is_see_through(c) && !is_low_ceiling(c)) {
+      return false;
+    }
+  }
+  true
+}
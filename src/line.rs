@@ -1,7 +1,7 @@
 // line.rs
 
-use raylib::prelude::*;
 use crate::framebuffer::Framebuffer;
+use crate::gfx::Vector2;
 
 pub fn line(
     framebuffer: &mut Framebuffer,
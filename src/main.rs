@@ -8,258 +8,186 @@ mod maze;
 mod caster;
 mod player;
 mod textures;
+#[cfg(feature = "window")]
 mod audio;
-
-use line::line;
-use maze::{Maze,load_maze};
-use caster::{cast_ray, Intersect};
+mod camera;
+mod doors;
+mod lighting;
+mod sprites;
+mod portals;
+mod viewmodel;
+mod decals;
+mod gfx;
+#[cfg(feature = "window")]
+mod display;
+mod headless;
+mod scene;
+mod renderer;
+#[cfg(test)]
+mod golden;
+
+use maze::{Maze,is_wall};
+use caster::cast_ray_all;
 use framebuffer::Framebuffer;
-use player::{Player, process_events};
-use textures::TextureManager;
+use player::Player;
+#[cfg(feature = "window")]
+use player::process_events;
+#[cfg(feature = "window")]
 use audio::AudioManager;
-
-use raylib::prelude::*;
+use viewmodel::ViewModel;
+use decals::Decal;
+use gfx::Vector2;
+#[cfg(feature = "window")]
+use display::Display;
+use scene::Scene;
+use renderer::{BACKGROUND_COLOR, render_maze};
+
+#[cfg(feature = "window")]
+use raylib::prelude::{KeyboardKey, RaylibDraw, RaylibDrawHandle, TraceLogLevel};
+#[cfg(feature = "window")]
+use raylib::color::Color as ScreenColor;
 use std::thread;
 use std::time::Duration;
 use std::f32::consts::PI;
 use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
 
-fn cell_to_color(cell: char) -> Color {
-  match cell {
-    '+' => {
-      return Color::BLUEVIOLET;
-    },
-    '-' => {
-      return Color::VIOLET;
-    },
-    '|' => {
-      return Color::VIOLET;
-    },
-    'g' => {
-      return Color::GREEN;
-    },
-    _ => {
-      return Color::WHITE;
-    },
-  }
-}
-
-fn draw_cell(
-  framebuffer: &mut Framebuffer,
-  xo: usize,
-  yo: usize,
-  block_size: usize,
-  cell: char,
-) {
-  if cell == ' ' {
-    return;
-  }
-  let color = cell_to_color(cell);
-  framebuffer.set_current_color(color);
-
-  for x in xo..xo + block_size {
-    for y in yo..yo + block_size {
-      framebuffer.set_pixel(x as u32, y as u32);
-    }
-  }
-}
+// F2 saves the 3D view here, named by the time it was taken
+const SCREENSHOT_DIR: &str = "screenshots";
 
-pub fn render_maze(
-  framebuffer: &mut Framebuffer,
-  maze: &Maze,
-  block_size: usize,
-  player: &Player,
-) {
-  for (row_index, row) in maze.iter().enumerate() {
-    for (col_index, &cell) in row.iter().enumerate() {
-      let xo = col_index * block_size;
-      let yo = row_index * block_size;
-      draw_cell(framebuffer, xo, yo, block_size, cell);
-    }
-  }
+// size of the window, and of frames rendered without one
+const WINDOW_WIDTH: i32 = 1300;
+const WINDOW_HEIGHT: i32 = 900;
+const BLOCK_SIZE: usize = 100;
 
-  framebuffer.set_current_color(Color::WHITESMOKE);
+// how close the player must get to a crumb or key to pick it up
+const PICKUP_RADIUS: f32 = 80.0;
 
-  // draw what the player sees
-  let num_rays = 5;
-  for i in 0..num_rays {
-    let current_ray = i as f32 / num_rays as f32; // current ray divided by total rays
-    let a = player.a - (player.fov / 2.0) + (player.fov * current_ray);
-    cast_ray(framebuffer, &maze, &player, a, block_size, true);
-  }
+// In the middle of the top-left corridor cell, looking down it
+fn start_player(block_size: usize) -> Player {
+  let block = block_size as f32;
+  Player::new(Vector2::new(1.5 * block, 1.5 * block), PI / 3.0)
 }
 
-fn render_world(
-  framebuffer: &mut Framebuffer,
-  maze: &Maze,
-  block_size: usize,
-  player: &Player,
-  texture_cache: &TextureManager,
-  crumbs: &[(usize, usize)],
-) {
-  let num_rays = framebuffer.width;
-
-  let hh = framebuffer.height as f32 / 2.0;  // precalculated half height
-  let distance_to_projection_plane = 70.0; // how far is the "player" from the "camera"
-
-  framebuffer.set_current_color(Color::WHITESMOKE);
-
-  // Store wall distances for depth testing
-  let mut wall_distances: Vec<f32> = vec![f32::MAX; num_rays as usize];
-
-  // Render walls with real textures from wall.png
-  for i in 0..num_rays {
-    let current_ray = i as f32 / num_rays as f32; // current ray divided by total rays
-    let a = player.a - (player.fov / 2.0) + (player.fov * current_ray);
-    let intersect = cast_ray(framebuffer, &maze, &player, a, block_size, false);
-
-    // Calculate the height of the stake
-    let distance_to_wall = intersect.distance;// how far is this wall from the player
-    // Store wall distance for depth testing
-    wall_distances[i as usize] = distance_to_wall;
-    
-    // this ratio doesn't really matter as long as it is a function of distance
-    let stake_height = (hh / distance_to_wall) * distance_to_projection_plane;
-
-    // Calculate the position to draw the stake
-    let stake_top = (hh - (stake_height / 2.0)) as usize;
-    let stake_bottom = (hh + (stake_height / 2.0)) as usize;
-
-              // Calculate texture coordinates for wall using wall.png
-     let hit_u = if intersect.vertical_side {
-       intersect.hit_y / block_size as f32
-     } else {
-       intersect.hit_x / block_size as f32
-     };
-     
-     // Get texture coordinates from wall.png (64x64)
-     let wall_tex_x = (hit_u * 64.0) as u32;
-     
-     for y in stake_top..stake_bottom {
-       if y >= framebuffer.height as usize { continue; }
-       
-       let v = (y as f32 - stake_top as f32) / (stake_bottom as f32 - stake_top as f32 + 0.0001);
-       let wall_tex_y = (v * 64.0) as u32;
-       
-       // Get real pixel color from wall.png texture
-       let wall_color = texture_cache.get_wall_pixel_color(wall_tex_x, wall_tex_y);
-       
-       framebuffer.set_current_color(wall_color);
-       framebuffer.set_pixel(i, y as u32);
-     }
+// how far off a wall the player can chalk an arrow on it, in blocks
+const CHALK_REACH: f32 = 1.5;
+
+// Arrow at eye height on the wall straight ahead, to find the way back
+// through the maze. Doors slide and mirrors and portals show somewhere
+// else, so those aren't marked.
+fn chalk_arrow(scene: &mut Scene, player: &Player) {
+  let hits = cast_ray_all(&scene.level, &scene.doors, player.pos, player.a, scene.block_size);
+  let Some(hit) = hits.first() else { return };
+  let height = player.eye_height();
+  let (i, j) = hit.cell;
+  if hit.distance > CHALK_REACH * scene.block_size as f32
+    || !is_wall(hit.impact)
+    || hit.onward.is_some()
+    || scene.doors.get(i, j).is_some()
+    || !(hit.bottom..hit.height).contains(&height)
+  {
+    return;
   }
+  scene.decals.add(Decal {
+    texture: "arrow".to_string(),
+    cell: hit.cell,
+    face: hit.face,
+    u: hit.tex_u,
+    height,
+    size: 0.3,
+  });
+}
 
-         // Render sprites (crumbs) as simple yellow dots
-   for (ci, cj) in crumbs {
-     let cx = (*ci as f32) * block_size as f32 + (block_size as f32 * 0.5);
-     let cy = (*cj as f32) * block_size as f32 + (block_size as f32 * 0.5);
-     
-     // Calculate sprite position relative to player
-     let dx = cx - player.pos.x;
-     let dy = cy - player.pos.y;
-     
-     // Calculate distance and angle to sprite
-     let sprite_distance = (dx * dx + dy * dy).sqrt();
-     let sprite_angle = dy.atan2(dx) - player.a;
-     
-     // Skip if sprite is behind player
-     if sprite_angle.abs() > PI / 2.0 {
-       continue;
-     }
-     
-     // Calculate sprite screen position
-     let sprite_screen_x = (sprite_angle / (player.fov / 2.0)) * (framebuffer.width as f32 / 2.0) + (framebuffer.width as f32 / 2.0);
-     
-     // Calculate sprite size on screen
-     let sprite_size = (block_size as f32 / sprite_distance) * distance_to_projection_plane;
-     let sprite_height = sprite_size.max(1.0) as usize;
-     let sprite_width = sprite_size.max(1.0) as usize;
-     
-     // Skip rendering if sprite is too small or too far
-     if sprite_width < 1 || sprite_height < 1 || sprite_distance > 1000.0 {
-       continue;
-     }
-     
-     // Draw sprite as simple yellow dot
-     let sprite_top = (hh - sprite_height as f32 / 2.0) as usize;
-     let sprite_bottom = (hh + sprite_height as f32 / 2.0) as usize;
-     
-     // Check if sprite is behind walls using depth testing
-     let sprite_start_x = (sprite_screen_x as usize).saturating_sub(sprite_width / 2);
-     let sprite_end_x = (sprite_screen_x as usize + sprite_width / 2).min(framebuffer.width as usize);
-     
-     for y in sprite_top..sprite_bottom {
-       if y >= framebuffer.height as usize { continue; }
-       
-       for x in sprite_start_x..sprite_end_x {
-         if x >= framebuffer.width as usize { continue; }
-         
-         // Depth test: only draw if sprite is closer than wall
-         if x < wall_distances.len() && sprite_distance < wall_distances[x] {
-           framebuffer.set_current_color(Color::YELLOW);
-           framebuffer.set_pixel(x as u32, y as u32);
-         }
-       }
-     }
-   }
+// Index of the first pickup within reach of the player
+fn pickup_in_reach(pickups: &[(usize, usize)], player: &Player, block_size: usize) -> Option<usize> {
+  pickups.iter().position(|&(ci, cj)| {
+    let cx = (ci as f32) * block_size as f32 + (block_size as f32 * 0.5);
+    let cy = (cj as f32) * block_size as f32 + (block_size as f32 * 0.5);
+    let dx = player.pos.x - cx;
+    let dy = player.pos.y - cy;
+    (dx*dx + dy*dy).sqrt() < PICKUP_RADIUS
+  })
 }
 
-fn draw_minimap(window: &mut RaylibHandle, rl: &RaylibThread, maze: &Maze, block_size: usize, player: &Player) {
+#[cfg(feature = "window")]
+fn draw_minimap(d: &mut RaylibDrawHandle, maze: &Maze, block_size: usize, player: &Player) {
   let scale: i32 = 4;
   let padding: i32 = 10;
   let width = (maze[0].len() as i32) * scale;
   let height = (maze.len() as i32) * scale;
-  let x0 = window.get_screen_width() - width - padding;
+  let x0 = d.get_screen_width() - width - padding;
   let y0 = padding;
-  let mut d = window.begin_drawing(rl);
-  d.draw_rectangle_lines(x0-1, y0-1, width+2, height+2, Color::WHITE);
+  d.draw_rectangle_lines(x0-1, y0-1, width+2, height+2, ScreenColor::WHITE);
   for j in 0..maze.len() {
     for i in 0..maze[0].len() {
       let c = maze[j][i];
       if c != ' ' {
-        d.draw_rectangle(x0 + (i as i32)*scale, y0 + (j as i32)*scale, scale, scale, Color::DARKGRAY);
+        d.draw_rectangle(x0 + (i as i32)*scale, y0 + (j as i32)*scale, scale, scale, ScreenColor::DARKGRAY);
       }
     }
   }
   let px = (player.pos.x as i32) / block_size as i32;
   let py = (player.pos.y as i32) / block_size as i32;
-  d.draw_rectangle(x0 + px*scale, y0 + py*scale, scale, scale, Color::YELLOW);
+  d.draw_rectangle(x0 + px*scale, y0 + py*scale, scale, scale, ScreenColor::YELLOW);
+}
+
+fn save_screenshot(framebuffer: &Framebuffer) {
+  let path = Path::new(SCREENSHOT_DIR).join(format!("{}.png", timestamp()));
+  let saved = fs::create_dir_all(SCREENSHOT_DIR).and_then(|_| framebuffer.render_to_file(&path));
+  match saved {
+    Ok(()) => println!("Saved {}", path.display()),
+    Err(err) => eprintln!("Could not save {}: {}", path.display(), err),
+  }
+}
+
+// Current UTC time like 2024-05-01_13-45-07.250, which sorts in time order
+fn timestamp() -> String {
+  let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+  let seconds = now.as_secs();
+  let time = seconds % 86400;
+
+  // civil date from days since 1970-01-01, after Howard Hinnant's days_from_civil inverse
+  let z = (seconds / 86400) as i64 + 719468;
+  let era = z.div_euclid(146097);
+  let day_of_era = z - era * 146097;
+  let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+  let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+  let mp = (5 * day_of_year + 2) / 153;
+  let day = day_of_year - (153 * mp + 2) / 5 + 1;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 };
+  let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+  format!(
+    "{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.{:03}",
+    year, month, day, time / 3600, time / 60 % 60, time % 60, now.subsec_millis()
+  )
 }
 
+#[cfg(feature = "window")]
 fn main() {
-  let window_width = 1300;
-  let window_height = 900;
-  let block_size = 100;
+  let block_size = BLOCK_SIZE;
 
   let (mut window, raylib_thread) = raylib::init()
-    .size(window_width, window_height)
+    .size(WINDOW_WIDTH, WINDOW_HEIGHT)
     .title("Raycaster Example")
     .log_level(TraceLogLevel::LOG_WARNING)
     .build();
 
-  let mut framebuffer = Framebuffer::new(window_width as u32, window_height as u32);
-  framebuffer.set_background_color(Color::new(50, 50, 100, 255));
+  let mut framebuffer = Framebuffer::new(WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32);
+  framebuffer.set_background_color(BACKGROUND_COLOR);
+  let mut display = Display::default();
 
-  let maze = load_maze("maze.txt");
-  // Collectible crumbs from 'g' cells
-  let mut crumbs: Vec<(usize, usize)> = Vec::new();
-  for (j, row) in maze.iter().enumerate() {
-    for (i, &c) in row.iter().enumerate() {
-      if c == 'g' { crumbs.push((i, j)); }
-    }
-  }
+  // The level with its doors, lights, sprites, pickups and decals
+  let mut scene = Scene::load("maze.txt", block_size);
 
-     // Initialize texture manager
-   let texture_cache = TextureManager::new(&mut window, &raylib_thread);
-   
        // Initialize audio manager
     let mut audio_manager = AudioManager::new(&mut window, &raylib_thread);
 
-  let mut player = Player {
-    pos: Vector2::new(150.0, 150.0),
-    a: PI / 3.0,
-    fov: PI / 3.0,
-  };
+  let mut player = start_player(block_size);
+
+  // Lantern held in first person, if the manifest has one; V hides it
+  let mut view_model = scene.texture_cache.has_sprite("lantern").then(|| ViewModel::new("lantern"));
 
   // Game state
   #[derive(PartialEq, Eq, Clone, Copy)]
@@ -276,7 +204,18 @@ fn main() {
 
     // 2. move the player on user input (only in Playing)
     if state == GameState::Playing {
-      process_events(&mut player, &window, &maze, block_size);
+      process_events(&mut player, &window, &scene.level, &mut scene.doors, block_size);
+      if window.is_key_pressed(KeyboardKey::KEY_T) {
+        chalk_arrow(&mut scene, &player);
+      }
+    }
+    let player_cell = ((player.pos.x as usize) / block_size, (player.pos.y as usize) / block_size);
+    scene.doors.update(window.get_frame_time(), player_cell);
+    if let Some(view_model) = view_model.as_mut() {
+      if window.is_key_pressed(KeyboardKey::KEY_V) {
+        view_model.visible = !view_model.visible;
+      }
+      view_model.update(&player, window.get_frame_time());
     }
 
     let mut mode = "3D";
@@ -286,38 +225,49 @@ fn main() {
     }
 
     // 3. draw stuff
+    let time = window.get_time() as f32;
+    scene.lighting.update(time);
     if state == GameState::Playing {
              if mode == "2D" {
-         render_maze(&mut framebuffer, &maze, block_size, &player);
+         render_maze(&mut framebuffer, &scene.level, &scene.doors, block_size, &player);
        } else {
-         render_world(&mut framebuffer, &maze, block_size, &player, &texture_cache, &crumbs);
+         scene.render(&mut framebuffer, &player, time);
+         if let Some(view_model) = &view_model {
+           view_model.render(&mut framebuffer, &player, &scene.texture_cache, &scene.lighting, time);
+         }
        }
     }
 
-         // 4. swap buffers
-     framebuffer.swap_buffers(&mut window, &raylib_thread);
-     
+    // screenshot of the frame as drawn so far, without the minimap and HUD
+    if window.is_key_pressed(KeyboardKey::KEY_F2) {
+      save_screenshot(&framebuffer);
+    }
+
      // Update background music
      if state == GameState::Playing {
        audio_manager.update_music(&mut window);
      }
-    
-    // HUD overlays and screens
+
+    // 4. swap buffers, then the minimap, HUD overlays and screens on top,
+    // all in the same frame
     {
-      let mut d = window.begin_drawing(&raylib_thread);
+      let mut d = display.swap_buffers(&framebuffer, &mut window, &raylib_thread);
+      if state == GameState::Playing {
+        draw_minimap(&mut d, &scene.level.maze, block_size, &player);
+      }
       match state {
         GameState::Start => {
           let sw = d.get_screen_width();
           let sh = d.get_screen_height();
-          d.clear_background(Color::new(30, 30, 60, 255));
-          d.draw_text("Presiona cualquier tecla para iniciar", sw/2 - 220, sh/2 - 10, 20, Color::WHITE);
-          d.draw_text("Recoge las 3 migajas para ganar!", sw/2 - 200, sh/2 + 20, 18, Color::YELLOW);
+          d.clear_background(ScreenColor::new(30, 30, 60, 255));
+          d.draw_text("Presiona cualquier tecla para iniciar", sw/2 - 220, sh/2 - 10, 20, ScreenColor::WHITE);
+          d.draw_text("Recoge las 3 migajas para ganar!", sw/2 - 200, sh/2 + 20, 18, ScreenColor::YELLOW);
         }
                  GameState::Playing => {
            d.draw_fps(10, 10);
            // Show crumb counter
-           let remaining = crumbs.len();
-           d.draw_text(&format!("Migajas restantes: {}/3", 3 - remaining), 10, 30, 20, Color::WHITE);
+           let remaining = scene.crumbs.len();
+           d.draw_text(&format!("Migajas restantes: {}/3", 3 - remaining), 10, 30, 20, ScreenColor::WHITE);
            
            // Show audio status
            let audio_status = if audio_manager.has_background_music() && audio_manager.has_pickup_sound() {
@@ -327,14 +277,15 @@ fn main() {
            } else {
              "Audio: OFF"
            };
-           d.draw_text(audio_status, 10, 50, 16, Color::YELLOW);
+           d.draw_text(audio_status, 10, 50, 16, ScreenColor::YELLOW);
+           d.draw_text(&format!("Llaves: {}", player.keys), 10, 70, 16, ScreenColor::GOLD);
          }
         GameState::Success => {
           let sw = d.get_screen_width();
           let sh = d.get_screen_height();
-          d.clear_background(Color::new(20, 60, 20, 255));
-          d.draw_text("¡Felicidades! Eres el mejor migajero", sw/2 - 260, sh/2 - 10, 20, Color::WHITE);
-          d.draw_text("Presiona ESC para salir", sw/2 - 150, sh/2 + 20, 18, Color::YELLOW);
+          d.clear_background(ScreenColor::new(20, 60, 20, 255));
+          d.draw_text("¡Felicidades! Eres el mejor migajero", sw/2 - 260, sh/2 - 10, 20, ScreenColor::WHITE);
+          d.draw_text("Presiona ESC para salir", sw/2 - 150, sh/2 + 20, 18, ScreenColor::YELLOW);
         }
       }
     }
@@ -352,28 +303,20 @@ fn main() {
     if state == GameState::Success && window.is_key_down(KeyboardKey::KEY_ESCAPE) {
       break;
     }
-    
-    if state == GameState::Playing { draw_minimap(&mut window, &raylib_thread, &maze, block_size, &player); }
 
     // Collectibles check in Playing state
     if state == GameState::Playing {
-      let mut collected = None;
-      for (idx, (ci, cj)) in crumbs.iter().enumerate() {
-        let cx = (*ci as f32) * block_size as f32 + (block_size as f32 * 0.5);
-        let cy = (*cj as f32) * block_size as f32 + (block_size as f32 * 0.5);
-        let dx = player.pos.x - cx;
-        let dy = player.pos.y - cy;
-        // Increased collection radius from 40 to 80 pixels for easier collection
-        if (dx*dx + dy*dy).sqrt() < 80.0 {
-          collected = Some(idx);
-          break;
-        }
+      if let Some(idx) = pickup_in_reach(&scene.keys, &player, block_size) {
+        scene.keys.remove(idx);
+        player.keys += 1;
+        audio_manager.play_pickup_sound(&mut window);
       }
+      let collected = pickup_in_reach(&scene.crumbs, &player, block_size);
              if let Some(idx) = collected {
-         crumbs.remove(idx);
+         scene.crumbs.remove(idx);
          // Play pickup sound effect
          audio_manager.play_pickup_sound(&mut window);
-         if crumbs.is_empty() {
+         if scene.crumbs.is_empty() {
            state = GameState::Success;
          }
        }
@@ -383,5 +326,19 @@ fn main() {
   }
 }
 
-
-
+// Without the window feature there is nothing to play in; render the view
+// from the start to the file given on the command line (PPM, BMP or PNG)
+#[cfg(not(feature = "window"))]
+fn main() {
+  let path = std::env::args().nth(1).unwrap_or_else(|| "frame.png".to_string());
+  let mut scene = Scene::load("maze.txt", BLOCK_SIZE);
+  let player = start_player(BLOCK_SIZE);
+  let framebuffer = headless::render_frame(&mut scene, &player, WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32, 0.0);
+  match framebuffer.render_to_file(Path::new(&path)) {
+    Ok(()) => println!("Saved {}", path),
+    Err(err) => {
+      eprintln!("Could not save {}: {}", path, err);
+      std::process::exit(1);
+    }
+  }
+}
@@ -1,17 +1,391 @@
 // maze.rs
 
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 
+use crate::caster::Face;
+use crate::decals::Decal;
+use crate::gfx::{Color, Vector2};
+use crate::lighting::{Fog, PointLight};
+use crate::portals::{PortalEnd, Portals};
+
 pub type Maze = Vec<Vec<char>>;
 
-pub fn load_maze(filename: &str) -> Maze {
+// A maze file is the wall grid, optionally followed by `[section]`
+// blocks. `[floor]` and `[ceiling]` hold grids of the same shape whose
+// characters pick the texture of each cell; ' ' and '.' mean default.
+// '~' in `[ceiling]` marks an outdoor cell: it has no ceiling and the
+// sky shows above it.
+// `[heights]` is a grid of wall heights in half blocks: '1' is a half
+// wall, '4' a two-block pillar; walls default to one block ('2').
+// `[lights]` holds one entry per line:
+//
+//   ambient <level>                          brightness without lights (0-1)
+//   light <x> <y> <r> <g> <b> <radius> <flicker>
+//   fog none
+//   fog linear <start> <end>                 clear up to `start`, only fog from `end`
+//   fog exp <density>                        1 - e^(-density * distance)
+//   fog_color <r> <g> <b>
+//
+// Light positions and radius and fog distances are in cells, flicker is
+// 0 (steady) to 1. Fog defaults to `fog exp 0.15` in `fog_color 10 10 20`.
+// `[sprites]` places one sprite per line:
+//
+//   <name> <x> <y> [facing] [size]
+//
+// with the name from assets/tiles.txt, position in cells, facing in
+// degrees (default 0) and height in blocks (default 0.5).
+// `[thin]` puts a thin wall on one edge of a cell, one per line:
+//
+//   <x> <y> <north|south|east|west> [tile]
+//
+// textured like wall tile `tile` (default '*'). '/' and '\' in the grid
+// are diagonal walls from corner to corner through the cell center.
+// `[mirrors]` sets up 'M' mirror walls:
+//
+//   bounces <n>                how many mirrors a ray reflects off (default 2)
+//   tint <r> <g> <b> <amount>  color mixed into every reflection, amount 0-1
+//
+// `[portals]` links two wall faces, one pair per line:
+//
+//   <x1> <y1> <face1> <x2> <y2> <face2>
+//
+// Rays and the player going into one face come out of the other.
+//
+// `[decals]` puts images on wall faces, one per line:
+//
+//   <name> <x> <y> <face> [u] [height] [size]
+//
+// with the name from assets/tiles.txt, the wall cell and face, the
+// center across the face (0-1, default 0.5) and above the floor (in
+// blocks, default 0.5), and the size in blocks (default 0.3).
+pub struct Level {
+    pub maze: Maze,
+    pub floor: Maze,
+    pub ceiling: Maze,
+    pub heights: Maze,
+    pub max_wall_height: f32, // tallest wall in the level, in blocks
+    pub ambient: f32,
+    pub lights: Vec<PointLight>,
+    pub fog: Fog, // in cells
+    pub fog_color: Color,
+    pub sprites: Vec<SpritePlacement>,
+    pub segments: HashMap<(usize, usize), Vec<Segment>>, // thin and diagonal walls by cell
+    pub mirror_bounces: u32,
+    pub mirror_tint: Color, // alpha is how much of the tint each reflection takes
+    pub portals: Portals,
+    pub decals: Vec<Decal>,
+}
+
+pub struct SpritePlacement {
+    pub name: String,
+    pub pos: Vector2, // in cells
+    pub facing: f32,  // radians
+    pub size: f32,    // in blocks
+}
+
+// Wall that does not fill its cell: a line from `from` to `to`, in cells
+#[derive(Clone, Copy)]
+pub struct Segment {
+    pub from: Vector2,
+    pub to: Vector2,
+    pub tile: char,
+}
+
+impl Segment {
+    // Distance from `point` to the nearest point of the segment, in cells
+    pub fn distance_to(&self, point: Vector2) -> f32 {
+        let e = self.to - self.from;
+        let w = point - self.from;
+        let s = ((w.x * e.x + w.y * e.y) / (e.x * e.x + e.y * e.y)).clamp(0.0, 1.0);
+        let dx = w.x - e.x * s;
+        let dy = w.y - e.y * s;
+        (dx * dx + dy * dy).sqrt()
+    }
+}
+
+impl Level {
+    pub fn floor_at(&self, i: usize, j: usize) -> char {
+        layer_at(&self.floor, i, j)
+    }
+
+    pub fn ceiling_at(&self, i: usize, j: usize) -> char {
+        layer_at(&self.ceiling, i, j)
+    }
+
+    pub fn is_outdoor(&self, i: usize, j: usize) -> bool {
+        self.ceiling_at(i, j) == '~'
+    }
+
+    // Whether cell (i, j) is a low ceiling; false outside the maze
+    pub fn has_low_ceiling(&self, i: usize, j: usize) -> bool {
+        self.maze.get(j).and_then(|row| row.get(i)).is_some_and(|&c| is_low_ceiling(c))
+    }
+
+    // Wall height of cell (i, j), in blocks
+    pub fn wall_height(&self, i: usize, j: usize) -> f32 {
+        match layer_at(&self.heights, i, j).to_digit(10) {
+            Some(half_blocks) if half_blocks > 0 => half_blocks as f32 / 2.0,
+            _ => 1.0,
+        }
+    }
+
+    // Thin and diagonal walls inside cell (i, j)
+    pub fn segments_at(&self, i: usize, j: usize) -> &[Segment] {
+        self.segments.get(&(i, j)).map_or(&[], |segments| segments.as_slice())
+    }
+}
+
+// Cells that stop rays and light. Pickups ('g' crumbs, 'k' keys) and
+// diagonal cells, which are half open, do not. Low ceilings count as
+// walls, but rays and light pass below them.
+pub fn is_wall(cell: char) -> bool {
+    cell != ' ' && cell != 'g' && cell != 'k' && !is_diagonal(cell)
+}
+
+// Underside of low-ceiling tiles ('='), in blocks. They hang from the
+// ceiling and only a crouching player fits below them.
+pub const LOW_CEILING: f32 = 0.4;
+
+pub fn is_low_ceiling(cell: char) -> bool {
+    cell == '='
+}
+
+// Mirror walls reflect rays; past the last bounce they are plain walls
+pub fn is_mirror(cell: char) -> bool {
+    cell == 'M'
+}
+
+pub fn is_diagonal(cell: char) -> bool {
+    cell == '/' || cell == '\\'
+}
+
+// Walls with holes in their texture ('#' grates, 'W' windows). They
+// block the player but not rays or light.
+pub fn is_see_through(cell: char) -> bool {
+    cell == '#' || cell == 'W'
+}
+
+fn layer_at(layer: &Maze, i: usize, j: usize) -> char {
+    match layer.get(j).and_then(|row| row.get(i)) {
+        Some(&c) if c != ' ' => c,
+        _ => '.',
+    }
+}
+
+pub fn load_level(filename: &str) -> Level {
     let file = File::open(filename).unwrap();
     let reader = BufReader::new(file);
 
-    reader
-        .lines()
-        .map(|line| line.unwrap().chars().collect())
-        .collect()
+    let mut level = Level {
+        maze: Vec::new(),
+        floor: Vec::new(),
+        ceiling: Vec::new(),
+        heights: Vec::new(),
+        max_wall_height: 0.0,
+        ambient: 1.0,
+        lights: Vec::new(),
+        fog: Fog::Exponential { density: 0.15 },
+        fog_color: Color::new(10, 10, 20, 255),
+        sprites: Vec::new(),
+        segments: HashMap::new(),
+        mirror_bounces: 2,
+        mirror_tint: Color::new(180, 200, 220, 40),
+        portals: Portals::default(),
+        decals: Vec::new(),
+    };
+    let mut section = String::new();
+
+    for line in reader.lines() {
+        let line = line.unwrap();
+        if line.starts_with('[') && line.trim_end().ends_with(']') {
+            section = line.trim().trim_matches(|c| c == '[' || c == ']').to_string();
+            continue;
+        }
+
+        let row: Vec<char> = line.chars().collect();
+        match section.as_str() {
+            "" => level.maze.push(row),
+            "floor" => level.floor.push(row),
+            "ceiling" => level.ceiling.push(row),
+            "heights" => level.heights.push(row),
+            "lights" => parse_light(&mut level, &line, filename),
+            "sprites" => parse_sprite(&mut level, &line, filename),
+            "thin" => parse_thin_wall(&mut level, &line, filename),
+            "mirrors" => parse_mirrors(&mut level, &line, filename),
+            "portals" => parse_portal(&mut level, &line, filename),
+            "decals" => parse_decal(&mut level, &line, filename),
+            other => panic!("Unknown section [{}] in {}", other, filename),
+        }
+    }
+
+    for (j, row) in level.maze.iter().enumerate() {
+        for (i, &cell) in row.iter().enumerate() {
+            let (x, y) = (i as f32, j as f32);
+            let (from, to) = match cell {
+                '/' => (Vector2::new(x, y + 1.0), Vector2::new(x + 1.0, y)),
+                '\\' => (Vector2::new(x, y), Vector2::new(x + 1.0, y + 1.0)),
+                _ => continue,
+            };
+            level.segments.entry((i, j)).or_default().push(Segment { from, to, tile: cell });
+        }
+    }
+
+    for (j, row) in level.maze.iter().enumerate() {
+        for (i, &cell) in row.iter().enumerate() {
+            if is_wall(cell) || level.segments.contains_key(&(i, j)) {
+                level.max_wall_height = level.max_wall_height.max(level.wall_height(i, j));
+            }
+        }
+    }
+
+    level
 }
 
+fn parse_light(level: &mut Level, line: &str, filename: &str) {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    // fog names its mode before the numbers
+    let words = if parts.first() == Some(&"fog") { 2 } else { 1 };
+    let numbers: Vec<f32> = parts
+        .iter()
+        .skip(words)
+        .map(|n| n.parse().unwrap_or_else(|_| panic!("Bad number `{}` in {}", n, filename)))
+        .collect();
+
+    match (&parts[..words.min(parts.len())], numbers.as_slice()) {
+        ([], _) => {}
+        (["ambient"], &[ambient]) => level.ambient = ambient,
+        (["light"], &[x, y, r, g, b, radius, flicker]) => level.lights.push(PointLight {
+            pos: Vector2::new(x, y),
+            color: Color::new(r as u8, g as u8, b as u8, 255),
+            radius,
+            flicker,
+        }),
+        (["fog", "none"], &[]) => level.fog = Fog::None,
+        (["fog", "linear"], &[start, end]) => level.fog = Fog::Linear { start, end },
+        (["fog", "exp"], &[density]) => level.fog = Fog::Exponential { density },
+        (["fog_color"], &[r, g, b]) => level.fog_color = Color::new(r as u8, g as u8, b as u8, 255),
+        _ => panic!("Malformed light entry `{}` in {}", line, filename),
+    }
+}
+
+fn parse_mirrors(level: &mut Level, line: &str, filename: &str) {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let numbers: Vec<f32> = parts
+        .iter()
+        .skip(1)
+        .map(|n| n.parse().unwrap_or_else(|_| panic!("Bad number `{}` in {}", n, filename)))
+        .collect();
+
+    match (parts.first(), numbers.as_slice()) {
+        (None, _) => {}
+        (Some(&"bounces"), &[bounces]) => level.mirror_bounces = bounces as u32,
+        (Some(&"tint"), &[r, g, b, amount]) => {
+            level.mirror_tint = Color::new(r as u8, g as u8, b as u8, (amount.clamp(0.0, 1.0) * 255.0) as u8)
+        }
+        _ => panic!("Malformed mirror entry `{}` in {}", line, filename),
+    }
+}
+
+fn parse_portal(level: &mut Level, line: &str, filename: &str) {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.is_empty() {
+        return;
+    }
+    let malformed = format!("Malformed portal entry `{}` in {}", line, filename);
+
+    let [x1, y1, face1, x2, y2, face2] = parts.as_slice() else { panic!("{}", malformed) };
+    let end = |x: &str, y: &str, face: &str| PortalEnd {
+        cell: (
+            x.parse().unwrap_or_else(|_| panic!("{}", malformed)),
+            y.parse().unwrap_or_else(|_| panic!("{}", malformed)),
+        ),
+        face: Face::from_name(face).unwrap_or_else(|| panic!("{}", malformed)),
+    };
+    level.portals.link(end(x1, y1, face1), end(x2, y2, face2));
+}
+
+fn parse_decal(level: &mut Level, line: &str, filename: &str) {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.is_empty() {
+        return;
+    }
+    let malformed = format!("Malformed decal entry `{}` in {}", line, filename);
+
+    let [name, x, y, face, rest @ ..] = parts.as_slice() else { panic!("{}", malformed) };
+    let numbers: Vec<f32> = rest
+        .iter()
+        .map(|n| n.parse().unwrap_or_else(|_| panic!("Bad number `{}` in {}", n, filename)))
+        .collect();
+    let (u, height, size) = match *numbers.as_slice() {
+        [] => (0.5, 0.5, 0.3),
+        [u] => (u, 0.5, 0.3),
+        [u, height] => (u, height, 0.3),
+        [u, height, size] => (u, height, size),
+        _ => panic!("{}", malformed),
+    };
+
+    level.decals.push(Decal {
+        texture: name.to_string(),
+        cell: (
+            x.parse().unwrap_or_else(|_| panic!("{}", malformed)),
+            y.parse().unwrap_or_else(|_| panic!("{}", malformed)),
+        ),
+        face: Face::from_name(face).unwrap_or_else(|| panic!("{}", malformed)),
+        u,
+        height,
+        size,
+    });
+}
+
+fn parse_sprite(level: &mut Level, line: &str, filename: &str) {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    let Some((name, args)) = parts.split_first() else {
+        return;
+    };
+    let numbers: Vec<f32> = args
+        .iter()
+        .map(|n| n.parse().unwrap_or_else(|_| panic!("Bad number `{}` in {}", n, filename)))
+        .collect();
+
+    let (x, y, facing, size) = match *numbers.as_slice() {
+        [x, y] => (x, y, 0.0, 0.5),
+        [x, y, facing] => (x, y, facing, 0.5),
+        [x, y, facing, size] => (x, y, facing, size),
+        _ => panic!("Malformed sprite entry `{}` in {}", line, filename),
+    };
+
+    level.sprites.push(SpritePlacement {
+        name: name.to_string(),
+        pos: Vector2::new(x, y),
+        facing: facing.to_radians(),
+        size,
+    });
+}
+
+fn parse_thin_wall(level: &mut Level, line: &str, filename: &str) {
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.is_empty() {
+        return;
+    }
+    let malformed = format!("Malformed thin wall entry `{}` in {}", line, filename);
+
+    let (x, y, edge, tile) = match parts.as_slice() {
+        [x, y, edge] => (x, y, edge, '*'),
+        [x, y, edge, tile] if tile.chars().count() == 1 => (x, y, edge, tile.chars().next().unwrap()),
+        _ => panic!("{}", malformed),
+    };
+    let i: usize = x.parse().unwrap_or_else(|_| panic!("{}", malformed));
+    let j: usize = y.parse().unwrap_or_else(|_| panic!("{}", malformed));
+
+    let (x, y) = (i as f32, j as f32);
+    let (from, to) = match *edge {
+        "north" => (Vector2::new(x, y), Vector2::new(x + 1.0, y)),
+        "south" => (Vector2::new(x, y + 1.0), Vector2::new(x + 1.0, y + 1.0)),
+        "west" => (Vector2::new(x, y), Vector2::new(x, y + 1.0)),
+        "east" => (Vector2::new(x + 1.0, y), Vector2::new(x + 1.0, y + 1.0)),
+        _ => panic!("{}", malformed),
+    };
+    level.segments.entry((i, j)).or_default().push(Segment { from, to, tile });
+}
@@ -1,19 +1,85 @@
 // player.rs
 
-use raylib::prelude::*;
+#[cfg(feature = "window")]
+use raylib::prelude::{KeyboardKey, RaylibHandle};
 use std::f32::consts::PI;
 
-use crate::maze::Maze;
+use crate::caster::Face;
+use crate::doors::Doors;
+use crate::gfx::Vector2;
+use crate::maze::{LOW_CEILING, Level, is_diagonal, is_low_ceiling};
 
 pub struct Player {
     pub pos: Vector2,
     pub a: f32,
     pub fov: f32, // field of view
+    pub keys: u32, // keys picked up, for locked doors
+    pub pitch: f32, // looking up (> 0) or down, as how far the horizon moves in screen heights
+    pub max_pitch: f32,
+    pub stance: f32,         // eye height standing or crouching, in blocks
+    pub jump: f32,           // how far a jump has lifted the eye above `stance`
+    pub vertical_speed: f32, // blocks per second, while in the air
+    pub crouching: bool,
+    pub velocity: Vector2, // blocks per second, from the last frame's movement
+    pub walking: f32,      // 0.0 standing still to 1.0 walking at full speed
+    pub bob_phase: f32,    // advances with the distance walked, drives head bob
 }
 
-pub fn process_events(player: &mut Player, rl: &RaylibHandle, maze: &Maze, block_size: usize) {
+// Eye heights in blocks
+pub const STAND_EYE: f32 = 0.5;
+pub const CROUCH_EYE: f32 = 0.25;
+// top of the head above the eye
+const HEAD_ROOM: f32 = 0.1;
+// how far the eye bobs up and down when walking, in blocks
+const HEAD_BOB: f32 = 0.015;
+// bob cycles per block walked
+const BOBS_PER_BLOCK: f32 = 1.5;
+
+impl Player {
+    // Standing still at `pos`, facing `a`
+    pub fn new(pos: Vector2, a: f32) -> Self {
+        Player {
+            pos,
+            a,
+            fov: PI / 3.0,
+            keys: 0,
+            pitch: 0.0,
+            max_pitch: 0.4,
+            stance: STAND_EYE,
+            jump: 0.0,
+            vertical_speed: 0.0,
+            crouching: false,
+            velocity: Vector2::new(0.0, 0.0),
+            walking: 0.0,
+            bob_phase: 0.0,
+        }
+    }
+
+    // Eye height above the floor, in blocks
+    pub fn eye_height(&self) -> f32 {
+        self.stance + self.jump + self.head_bob()
+    }
+
+    // Vertical eye offset from walking, in blocks; nothing in the air
+    pub fn head_bob(&self) -> f32 {
+        if self.jump > 0.0 {
+            return 0.0;
+        }
+        HEAD_BOB * self.bob_phase.sin() * self.walking
+    }
+}
+
+#[cfg(feature = "window")]
+pub fn process_events(player: &mut Player, rl: &RaylibHandle, level: &Level, doors: &mut Doors, block_size: usize) {
     const MOVE_SPEED: f32 = 6.0;
     const ROTATION_SPEED: f32 = PI / 10.0;
+    // closest the player gets to a thin or diagonal wall, in blocks
+    const SEGMENT_CLEARANCE: f32 = 0.15;
+    const JUMP_SPEED: f32 = 1.8;  // blocks per second
+    const GRAVITY: f32 = 6.0;     // blocks per second squared
+    const CROUCH_SPEED: f32 = 1.5; // blocks per second
+
+    let maze = &level.maze;
 
     // Keyboard rotation
     if rl.is_key_down(KeyboardKey::KEY_LEFT) {
@@ -23,38 +89,138 @@ pub fn process_events(player: &mut Player, rl: &RaylibHandle, maze: &Maze, block
         player.a -= ROTATION_SPEED;
     }
 
-    // Mouse horizontal rotation
+    // Mouse horizontal rotation and vertical look
     let mouse_delta = rl.get_mouse_delta();
     player.a -= mouse_delta.x * 0.003;
+    player.pitch = (player.pitch - mouse_delta.y * 0.002).clamp(-player.max_pitch, player.max_pitch);
 
     // Normalize angle to [0, 2PI)
     if player.a >= 2.0 * PI { player.a -= 2.0 * PI; }
     if player.a < 0.0 { player.a += 2.0 * PI; }
 
-    // Attempt movement with collision detection
-    let move_dir_forward = Vector2::new(player.a.cos(), player.a.sin());
-    let move_dir_backward = Vector2::new(-player.a.cos(), -player.a.sin());
+    // Use key opens the door in front of the player
+    if rl.is_key_pressed(KeyboardKey::KEY_E) {
+        let reach = block_size as f32 * 0.75;
+        let x = player.pos.x + player.a.cos() * reach;
+        let y = player.pos.y + player.a.sin() * reach;
+        if x >= 0.0 && y >= 0.0 {
+            doors.use_door(x as usize / block_size, y as usize / block_size, player.keys > 0);
+        }
+    }
+
+    // Crouch while C is held, and always under a low ceiling, where there
+    // is no room to stand up or jump
+    let dt = rl.get_frame_time();
+    let (ci, cj) = ((player.pos.x as usize) / block_size, (player.pos.y as usize) / block_size);
+    let under_low_ceiling = level.has_low_ceiling(ci, cj);
+    player.crouching = rl.is_key_down(KeyboardKey::KEY_C) || under_low_ceiling;
+    let stance = if player.crouching { CROUCH_EYE } else { STAND_EYE };
+    player.stance += (stance - player.stance).clamp(-CROUCH_SPEED * dt, CROUCH_SPEED * dt);
 
-    let try_move = |pos: Vector2, dir: Vector2| -> Vector2 {
+    // Jump from the ground with space, fall back down and land
+    let airborne = player.jump > 0.0 || player.vertical_speed != 0.0;
+    if !airborne && !under_low_ceiling && rl.is_key_pressed(KeyboardKey::KEY_SPACE) {
+        player.vertical_speed = JUMP_SPEED;
+    }
+    if player.jump > 0.0 || player.vertical_speed != 0.0 {
+        player.jump += player.vertical_speed * dt;
+        player.vertical_speed -= GRAVITY * dt;
+        if player.jump <= 0.0 {
+            player.jump = 0.0;
+            player.vertical_speed = 0.0;
+        }
+    }
+    // low ceilings only let a crouching player through
+    let head = player.eye_height() + HEAD_ROOM;
+
+    // thin walls on a cell edge belong to only one of the two cells, and
+    // the clearance reaches into the cells around, so check all nine
+    let near_segment = |target: Vector2| {
+        let point = Vector2::new(target.x / block_size as f32, target.y / block_size as f32);
+        let (i, j) = (point.x as usize, point.y as usize);
+        (j.saturating_sub(1)..=j + 1).any(|j| {
+            (i.saturating_sub(1)..=i + 1).any(|i| {
+                level.segments_at(i, j).iter().any(|segment| segment.distance_to(point) < SEGMENT_CLEARANCE)
+            })
+        })
+    };
+
+    // Attempt movement with collision detection. Returns the new position
+    // and how much the player turned, which only happens through portals.
+    let try_move = |pos: Vector2, dir: Vector2| -> (Vector2, f32) {
         let target = Vector2::new(pos.x + dir.x, pos.y + dir.y);
         let i = (target.x as usize) / block_size;
         let j = (target.y as usize) / block_size;
-        if j < maze.len() && i < maze[0].len() && maze[j][i] == ' ' {
-            target
+        let from = ((pos.x as usize) / block_size, (pos.y as usize) / block_size);
+
+        // walking into a portal face comes out of the other end
+        let entered = if i > from.0 {
+            Some(Face::West)
+        } else if i < from.0 {
+            Some(Face::East)
+        } else if j > from.1 {
+            Some(Face::North)
+        } else if j < from.1 {
+            Some(Face::South)
         } else {
-            pos
+            None
+        };
+        if let Some(portal) = entered.and_then(|face| level.portals.through((i, j), face)) {
+            let block = block_size as f32;
+            let exit = portal.point(Vector2::new(target.x / block, target.y / block));
+            return (Vector2::new(exit.x * block, exit.y * block), portal.rotation());
+        }
+
+        // closed doors block movement like walls
+        if j < maze.len() && i < maze[0].len()
+            && (maze[j][i] == ' '
+                || is_diagonal(maze[j][i])
+                || doors.is_passable(i, j)
+                || (is_low_ceiling(maze[j][i]) && head < LOW_CEILING))
+            && !near_segment(target)
+        {
+            (target, 0.0)
+        } else {
+            (pos, 0.0)
         }
     };
 
+    // small steps to avoid tunneling; the heading is read every step as a
+    // portal on the way can turn the player. Returns whether it moved.
+    let step = |player: &mut Player, sign: f32| -> bool {
+        let dir = Vector2::new(sign * player.a.cos(), sign * player.a.sin());
+        let (pos, turn) = try_move(player.pos, dir);
+        let moved = pos != player.pos;
+        player.pos = pos;
+        player.a = (player.a + turn).rem_euclid(2.0 * PI);
+        moved
+    };
+
+    // world units walked along the heading this frame, negative backwards
+    let mut walked: f32 = 0.0;
     if rl.is_key_down(KeyboardKey::KEY_DOWN) {
-        // move backward with small steps to avoid tunneling
         for _ in 0..(MOVE_SPEED as i32) {
-            player.pos = try_move(player.pos, Vector2::new(move_dir_backward.x, move_dir_backward.y));
+            if step(player, -1.0) {
+                walked -= 1.0;
+            }
         }
     }
     if rl.is_key_down(KeyboardKey::KEY_UP) {
         for _ in 0..(MOVE_SPEED as i32) {
-            player.pos = try_move(player.pos, Vector2::new(move_dir_forward.x, move_dir_forward.y));
+            if step(player, 1.0) {
+                walked += 1.0;
+            }
         }
     }
+
+    // Velocity, head bob and how much the player is walking, eased so a
+    // stop doesn't snap the view
+    let block = block_size as f32;
+    if dt > 0.0 {
+        let speed = walked / block / dt;
+        player.velocity = Vector2::new(player.a.cos() * speed, player.a.sin() * speed);
+    }
+    let walking = (walked.abs() / MOVE_SPEED).min(1.0);
+    player.walking += (walking - player.walking) * (10.0 * dt).min(1.0);
+    player.bob_phase = (player.bob_phase + walked.abs() / block * BOBS_PER_BLOCK * 2.0 * PI).rem_euclid(4.0 * PI);
 }
@@ -0,0 +1,109 @@
+// portals.rs
+
+use crate::caster::Face;
+use crate::gfx::Vector2;
+
+// Rays or the player crossing more portals than this in a row see the
+// portal wall instead, so two portals facing each other stay finite
+pub const MAX_PORTAL_CROSSINGS: u32 = 8;
+
+// One end of a portal: the given face of a wall cell
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PortalEnd {
+    pub cell: (usize, usize),
+    pub face: Face,
+}
+
+impl PortalEnd {
+    // Middle of the face, in cells
+    fn center(&self) -> Vector2 {
+        let normal = self.face.normal();
+        Vector2::new(
+            self.cell.0 as f32 + 0.5 + normal.x * 0.5,
+            self.cell.1 as f32 + 0.5 + normal.y * 0.5,
+        )
+    }
+
+    // The open cell in front of the face
+    pub fn front(&self) -> (isize, isize) {
+        let normal = self.face.normal();
+        (self.cell.0 as isize + normal.x as isize, self.cell.1 as isize + normal.y as isize)
+    }
+}
+
+// Carries what goes into one end of a portal out of the other end. It is
+// a rotation plus a translation, so left and right are kept.
+#[derive(Clone, Copy)]
+pub struct Transform {
+    from: Vector2,
+    to: Vector2,
+    rotation: f32,
+    exit_cell: (isize, isize),
+}
+
+impl Transform {
+    fn between(entry: PortalEnd, exit: PortalEnd) -> Self {
+        // going into the entry face means moving against its normal;
+        // coming out of the exit face means moving along its normal
+        let inward = entry.face.normal();
+        let outward = exit.face.normal();
+        Transform {
+            from: entry.center(),
+            to: exit.center(),
+            rotation: outward.y.atan2(outward.x) - (-inward.y).atan2(-inward.x),
+            exit_cell: exit.front(),
+        }
+    }
+
+    // Point in cells
+    pub fn point(&self, point: Vector2) -> Vector2 {
+        let offset = self.direction(Vector2::new(point.x - self.from.x, point.y - self.from.y));
+        Vector2::new(self.to.x + offset.x, self.to.y + offset.y)
+    }
+
+    pub fn direction(&self, dir: Vector2) -> Vector2 {
+        let (sin, cos) = self.rotation.sin_cos();
+        Vector2::new(dir.x * cos - dir.y * sin, dir.x * sin + dir.y * cos)
+    }
+
+    // Open cell in front of the exit face, where things come out
+    pub fn exit_cell(&self) -> (isize, isize) {
+        self.exit_cell
+    }
+
+    // How much a heading turns going through
+    pub fn rotation(&self) -> f32 {
+        self.rotation
+    }
+}
+
+// Portal pairs of a level. Pairs work both ways.
+#[derive(Default)]
+pub struct Portals {
+    pairs: Vec<(PortalEnd, PortalEnd)>,
+}
+
+impl Portals {
+    pub fn link(&mut self, a: PortalEnd, b: PortalEnd) {
+        self.pairs.push((a, b));
+    }
+
+    // Where something entering cell `cell` through `face` comes out, if
+    // that face is a portal
+    pub fn through(&self, cell: (usize, usize), face: Face) -> Option<Transform> {
+        let end = PortalEnd { cell, face };
+        self.pairs.iter().find_map(|&(a, b)| {
+            if a == end {
+                Some(Transform::between(a, b))
+            } else if b == end {
+                Some(Transform::between(b, a))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn ends(&self) -> impl Iterator<Item = &PortalEnd> {
+        self.pairs.iter().flat_map(|(a, b)| [a, b])
+    }
+}
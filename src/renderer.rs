@@ -0,0 +1,372 @@
+// renderer.rs
+//
+// The 3D view and the 2D map, drawn into a framebuffer. Nothing here
+// needs a window, so the game, headless rendering and the golden tests
+// all draw with it.
+
+use std::f32::consts::PI;
+
+use crate::camera::Camera;
+use crate::caster::{Face, Intersect, cast_ray, cast_ray_all};
+use crate::decals::Decals;
+use crate::doors::Doors;
+use crate::framebuffer::Framebuffer;
+use crate::gfx::{Color, Vector2};
+use crate::lighting::{Lighting, mix};
+use crate::line::line;
+use crate::maze::{LOW_CEILING, Level, is_diagonal, is_mirror};
+use crate::player::Player;
+use crate::sprites::{ALPHA_CUTOFF, Sprite, render_sprites};
+use crate::textures::TextureManager;
+
+// what shows where nothing is drawn
+pub const BACKGROUND_COLOR: Color = Color::new(50, 50, 100, 255);
+
+fn cell_to_color(cell: char) -> Color {
+  match cell {
+    '+' => Color::BLUEVIOLET,
+    '-' => Color::VIOLET,
+    '|' => Color::VIOLET,
+    'g' => Color::GREEN,
+    'k' => Color::GOLD,
+    'D' => Color::BROWN,
+    'L' => Color::MAROON,
+    '#' => Color::GRAY,
+    'W' => Color::SKYBLUE,
+    'M' => Color::LIGHTGRAY,
+    '=' => Color::DARKBROWN,
+    _ => Color::WHITE,
+  }
+}
+
+fn draw_cell(
+  framebuffer: &mut Framebuffer,
+  xo: usize,
+  yo: usize,
+  block_size: usize,
+  cell: char,
+) {
+  // diagonal walls are drawn as lines by render_maze
+  if cell == ' ' || is_diagonal(cell) {
+    return;
+  }
+  let color = cell_to_color(cell);
+  framebuffer.set_current_color(color);
+
+  for x in xo..xo + block_size {
+    for y in yo..yo + block_size {
+      framebuffer.set_pixel(x as u32, y as u32);
+    }
+  }
+}
+
+pub fn render_maze(
+  framebuffer: &mut Framebuffer,
+  level: &Level,
+  doors: &Doors,
+  block_size: usize,
+  player: &Player,
+) {
+  for (row_index, row) in level.maze.iter().enumerate() {
+    for (col_index, &cell) in row.iter().enumerate() {
+      let xo = col_index * block_size;
+      let yo = row_index * block_size;
+      draw_cell(framebuffer, xo, yo, block_size, cell);
+    }
+  }
+
+  // thin and diagonal walls
+  framebuffer.set_current_color(Color::VIOLET);
+  let block = block_size as f32;
+  for segment in level.segments.values().flatten() {
+    line(framebuffer, segment.from * block, segment.to * block);
+  }
+
+  // portal faces
+  framebuffer.set_current_color(Color::PURPLE);
+  for end in level.portals.ends() {
+    let normal = end.face.normal();
+    let center = Vector2::new(end.cell.0 as f32 + 0.5 + normal.x * 0.5, end.cell.1 as f32 + 0.5 + normal.y * 0.5);
+    let half = Vector2::new(-normal.y * 0.5, normal.x * 0.5);
+    line(framebuffer, (center - half) * block, (center + half) * block);
+  }
+
+  framebuffer.set_current_color(Color::WHITESMOKE);
+
+  // draw what the player sees
+  let num_rays = 5;
+  let camera = Camera::new(player, num_rays, framebuffer.height);
+  for i in 0..num_rays {
+    let a = camera.ray_angle(i);
+    cast_ray(framebuffer, level, doors, player, a, block_size, true);
+  }
+}
+
+// How much of the mirror tint shows after `bounces` mirrors, each of
+// which mixes in a little more
+fn reflection(tint: Color, bounces: u32) -> f32 {
+  1.0 - (1.0 - tint.a as f32 / 255.0).powi(bounces as i32)
+}
+
+// Cast floor and ceiling one screen row at a time. Every pixel of a row
+// below the horizon sees the floor at the same depth, at that depth down
+// its column's ray; past a mirror or portal in `hits`, down the ray that
+// went on from it. Looking up or down moves the horizon, not the rows.
+// Outdoor cells show the sky panorama instead of a ceiling (or the
+// background color when there is no panorama). From below a low ceiling,
+// the underside of low-ceiling cells is cast the same way; it goes into
+// `depth_buffer` so walls and sprites behind it stay hidden.
+#[allow(clippy::too_many_arguments)]
+fn render_floor_ceiling(
+  framebuffer: &mut Framebuffer,
+  level: &Level,
+  block_size: usize,
+  camera: &Camera,
+  texture_cache: &TextureManager,
+  lighting: &Lighting,
+  hits: &[Vec<Intersect>],
+  depth_buffer: &mut [f32],
+) {
+  let block = block_size as f32;
+  let width = framebuffer.width;
+  let eye = camera.eye * block; // eye height above the floor
+  let low_ceiling = LOW_CEILING * block;
+
+  // each column's ray, and the mirrors and portals it went on from
+  let rays: Vec<Vector2> = (0..width).map(|x| camera.ray_dir(x)).collect();
+  let through: Vec<Vec<&Intersect>> = hits.iter().map(|column| column.iter().filter(|hit| hit.onward.is_some()).collect()).collect();
+
+  // Where the point at `depth` down column `x`'s ray, `z` above the floor,
+  // really is: past a mirror or portal it lies along the ray that went on
+  // from there. The ray only goes through ones it meets between their
+  // bottom and top, not over them. Also gives the ray's direction there
+  // and the mirrors it bounced off.
+  let follow = |x: u32, depth: f32, z: f32| -> (Vector2, Vector2, u32) {
+    let ray = rays[x as usize];
+    let length = (ray.x * ray.x + ray.y * ray.y).sqrt();
+    let along = depth * length;
+    let (mut pos, mut dir, mut start, mut bounces) = (camera.pos, ray * (1.0 / length), 0.0, 0);
+    for hit in &through[x as usize] {
+      let height = eye + (z - eye) * hit.distance / along;
+      if hit.distance >= along || height < hit.bottom * block || height > hit.height * block {
+        break;
+      }
+      let onward = hit.onward.unwrap();
+      (pos, dir, start, bounces) = (onward.pos, onward.dir, hit.distance, onward.bounces);
+    }
+    (pos + dir * (along - start), dir, bounces)
+  };
+  let tint = level.mirror_tint;
+
+  // The sky wraps once around the player, so each column's ray angle
+  // picks its horizontal texture coordinate. Vertically it spans from the
+  // horizon to straight up, and each row's angle above the horizon picks
+  // the vertical coordinate, so looking up moves over the panorama
+  // instead of past its top edge.
+  let sky = texture_cache.sky();
+  // on-screen height of the whole panorama, near the horizon
+  let sky_height = camera.projection_distance * PI / 2.0;
+
+  for y in 0..framebuffer.height {
+    // pixel rows measured from the horizon; rows below it see the floor,
+    // rows above it the ceiling one block up
+    let p = y as f32 + 0.5 - camera.horizon;
+    let is_floor = p > 0.0;
+    let plane_height = if is_floor { eye } else { block - eye };
+    let row_distance = camera.project_size(plane_height, p.abs());
+    // on-screen size of one cell at this depth, for mip selection
+    let cell_size = camera.project_size(block, row_distance);
+    let plane_z = if is_floor { 0.0 } else { block };
+
+    // rows above the horizon meet the underside of low ceilings before
+    // the ceiling of the room, if the eye is below them
+    let underside_distance = (!is_floor && eye < low_ceiling).then(|| camera.project_size(low_ceiling - eye, -p));
+
+    for x in 0..width {
+      if let Some(distance) = underside_distance {
+        let (under, _, bounces) = follow(x, distance, low_ceiling);
+        let (under_x, under_y) = (under.x / block, under.y / block);
+        if under_x >= 0.0 && under_y >= 0.0 && level.has_low_ceiling(under_x as usize, under_y as usize) {
+          let (i, j) = (under_x as usize, under_y as usize);
+          let underside_size = camera.project_size(block, distance);
+          let color = texture_cache.get_wall_pixel_color(level.maze[j][i], Face::North, under_x.fract(), under_y.fract(), underside_size);
+          let light = lighting.illumination(under, (i, j));
+          let lit = lighting.apply(color, distance, lighting.north_south_shade, light);
+          framebuffer.set_current_color(mix(lit, tint, reflection(tint, bounces)));
+          framebuffer.set_pixel(x, y);
+          depth_buffer[(y * width + x) as usize] = distance;
+          continue;
+        }
+      }
+
+      let (point, dir, bounces) = follow(x, row_distance, plane_z);
+      let cell_x = point.x / block;
+      let cell_y = point.y / block;
+      if cell_x < 0.0 || cell_y < 0.0 {
+        continue;
+      }
+      let (i, j) = (cell_x as usize, cell_y as usize);
+      let (u, v) = (cell_x.fract(), cell_y.fract());
+
+      if is_floor {
+        let light = lighting.illumination(point, (i, j));
+        let floor_color = texture_cache.get_floor_pixel_color(level.floor_at(i, j), u, v, cell_size);
+        let lit = lighting.apply(floor_color, row_distance, 1.0, light);
+        framebuffer.set_current_color(mix(lit, tint, reflection(tint, bounces)));
+        framebuffer.set_pixel(x, y);
+        continue;
+      }
+
+      if level.is_outdoor(i, j) {
+        // the sky is infinitely far away: no fog, no lights
+        if let Some(sky) = sky {
+          let sky_u = dir.y.atan2(dir.x) / (2.0 * PI);
+          // p is negative above the horizon
+          let elevation = (-p / camera.projection_distance).atan();
+          let sky_v = 1.0 - elevation / (PI / 2.0);
+          framebuffer.set_current_color(mix(sky.sample(sky_u, sky_v, sky_height), tint, reflection(tint, bounces)));
+          framebuffer.set_pixel(x, y);
+        }
+        continue;
+      }
+
+      let light = lighting.illumination(point, (i, j));
+      let ceiling_color = texture_cache.get_ceiling_pixel_color(level.ceiling_at(i, j), u, v, cell_size);
+      let lit = lighting.apply(ceiling_color, row_distance, 1.0, light);
+      framebuffer.set_current_color(mix(lit, tint, reflection(tint, bounces)));
+      framebuffer.set_pixel(x, y);
+    }
+  }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn render_world(
+  framebuffer: &mut Framebuffer,
+  level: &Level,
+  doors: &Doors,
+  block_size: usize,
+  player: &Player,
+  texture_cache: &TextureManager,
+  lighting: &Lighting,
+  sprites: &[Sprite],
+  decals: &Decals,
+  time: f32,
+) {
+  let num_rays = framebuffer.width;
+
+  let camera = Camera::new(player, framebuffer.width, framebuffer.height);
+
+  // Per-pixel depth of the nearest wall or low ceiling, for occluding sprites
+  let width = framebuffer.width as usize;
+  let mut depth_buffer: Vec<f32> = vec![f32::MAX; width * framebuffer.height as usize];
+
+  // Every wall along each column's ray; floor casting needs to know where
+  // the rays went through mirrors and portals
+  let hits: Vec<Vec<Intersect>> = (0..num_rays).map(|i| cast_ray_all(level, doors, player.pos, camera.ray_angle(i), block_size)).collect();
+
+  // Floor and ceiling first, walls are drawn over them
+  render_floor_ceiling(framebuffer, level, block_size, &camera, texture_cache, lighting, &hits, &mut depth_buffer);
+
+  framebuffer.set_current_color(Color::WHITESMOKE);
+
+  let block = block_size as f32;
+  let eye = camera.eye * block; // eye height above the floor
+  // screen row of a point `z` above the floor at `depth`
+  let screen_y = |z: f32, depth: f32| camera.horizon + camera.project_size(eye - z, depth);
+
+  // Render walls with the textures registered in assets/tiles.txt
+  for (i, hits) in (0..num_rays).zip(&hits) {
+    let a = camera.ray_angle(i);
+
+    // Farthest first, so nearer (possibly shorter or see-through) walls
+    // paint over what they hide, and mirrors over what they reflect
+    for intersect in hits.iter().rev() {
+      // perpendicular distance to the camera plane, not the Euclidean one (no fisheye).
+      // Reflections are seen as if the mirror were a window, so this uses the whole
+      // length of the ray rather than where the hit is.
+      let distance_to_wall = intersect.distance * (a - player.a).cos();
+
+      // on-screen size of one block at this depth; textures repeat every block
+      let block_height = camera.project_size(block, distance_to_wall);
+      let wall_top = screen_y(intersect.height * block, distance_to_wall);
+      let wall_bottom = screen_y(intersect.bottom * block, distance_to_wall);
+      let floor_y = screen_y(0.0, distance_to_wall);
+
+      // Calculate the position to draw the stake. The top of a wall below
+      // eye level can be under the bottom of the screen.
+      let stake_top = (wall_top.max(0.0) as usize).min(framebuffer.height as usize);
+      let stake_bottom = (wall_bottom.max(0.0) as usize).min(framebuffer.height as usize);
+
+      // Point lights are evaluated once per column, just in front of the wall
+      let normal = intersect.face.normal();
+      let wall_light = lighting.illumination_at(Vector2::new(intersect.hit_x + normal.x, intersect.hit_y + normal.y));
+      let shade = lighting.face_shade(intersect.face);
+
+      // every mirror on the way mixes in a little of the mirror tint
+      let tint = level.mirror_tint;
+      let reflection = reflection(tint, intersect.bounces);
+
+      let wall_decals = decals.on(intersect.cell, intersect.face);
+
+      for y in stake_top..stake_bottom {
+        // the underside of a nearer low ceiling hides this part
+        if depth_buffer[y * width + i as usize] < distance_to_wall {
+          continue;
+        }
+        // measured up from the floor, so short walls show the bottom of the texture
+        let v = (y as f32 - floor_y) / block_height;
+
+        // Get real pixel color from the texture registered for this tile and face
+        let mut wall_color = texture_cache.get_wall_pixel_color(intersect.impact, intersect.face, intersect.tex_u, v, block_height);
+        // what the ray saw through a portal or in a mirror is already drawn;
+        // the wall still hides sprites behind it
+        if intersect.onward.is_some() && (wall_color.a == 0 || !is_mirror(intersect.impact)) {
+          depth_buffer[y * width + i as usize] = distance_to_wall;
+          continue;
+        }
+        if wall_color.a == 0 && is_mirror(intersect.impact) {
+          // out of bounces, the glass shows only its tint
+          wall_color = Color::new(tint.r, tint.g, tint.b, 255);
+        }
+        // holes let the walls behind show through, tinted glass blends over them
+        if wall_color.a == 0 {
+          continue;
+        }
+        if !wall_decals.is_empty() {
+          // v runs down the screen, so the height above the floor is -v
+          wall_color = Decals::apply(wall_decals, texture_cache, wall_color, intersect.tex_u, -v, block_height);
+        }
+        let lit = lighting.apply(wall_color, distance_to_wall, shade, wall_light);
+        framebuffer.set_current_color(mix(lit, tint, reflection));
+        framebuffer.blend_pixel(i, y as u32);
+        if wall_color.a >= ALPHA_CUTOFF {
+          depth_buffer[y * width + i as usize] = distance_to_wall;
+        }
+      }
+
+      // Walls below eye level show their top between where the ray enters and leaves the cell
+      let top = intersect.height * block;
+      if top < eye && intersect.exit_distance > intersect.distance {
+        let exit_depth = distance_to_wall * intersect.exit_distance / intersect.distance;
+        let cap_top = (screen_y(top, exit_depth).max(0.0) as usize).min(stake_top);
+        let cap_color = texture_cache.get_wall_pixel_color(intersect.impact, intersect.face, intersect.tex_u, 0.0, block_height);
+        if cap_color.a > 0 {
+          let lit = lighting.apply(cap_color, distance_to_wall, lighting.north_south_shade, wall_light);
+          framebuffer.set_current_color(mix(lit, tint, reflection));
+          for y in cap_top..stake_top {
+            if depth_buffer[y * width + i as usize] < distance_to_wall {
+              continue;
+            }
+            framebuffer.blend_pixel(i, y as u32);
+            if cap_color.a >= ALPHA_CUTOFF {
+              depth_buffer[y * width + i as usize] = distance_to_wall;
+            }
+          }
+        }
+      }
+    }
+  }
+
+  // Billboards go last, occluded by the walls drawn above
+  render_sprites(framebuffer, &camera, sprites, texture_cache, lighting, &depth_buffer, block_size, time);
+}
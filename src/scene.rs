@@ -0,0 +1,117 @@
+// scene.rs
+//
+// A level and everything in it, set up the same way for the game and for
+// headless rendering: doors, lights, sprites, the crumbs and keys to pick
+// up, and decals.
+
+use crate::decals::Decals;
+use crate::doors::Doors;
+use crate::framebuffer::Framebuffer;
+use crate::gfx::Vector2;
+use crate::lighting::Lighting;
+use crate::maze::{Level, load_level};
+use crate::player::Player;
+use crate::renderer::render_world;
+use crate::sprites::Sprite;
+use crate::textures::TextureManager;
+
+// crumb billboards are a quarter of a block tall
+const CRUMB_SIZE: f32 = 0.25;
+
+pub struct Scene {
+    pub level: Level,
+    pub doors: Doors,
+    pub texture_cache: TextureManager,
+    pub lighting: Lighting,          // distance fog, per-face wall shading and the level's point lights
+    pub decorations: Vec<Sprite>,    // sprites placed in the level file
+    pub crumbs: Vec<(usize, usize)>, // cells of the crumbs ('g') left to collect
+    pub keys: Vec<(usize, usize)>,   // cells of the keys ('k') left, which open locked doors ('L')
+    pub decals: Decals,              // from the level file; more can be added while playing
+    pub block_size: usize,
+}
+
+impl Scene {
+    // Panics if the level uses sprites or decals assets/tiles.txt doesn't have
+    pub fn load(path: &str, block_size: usize) -> Self {
+        let level = load_level(path);
+        let doors = Doors::from_maze(&level.maze);
+        let texture_cache = TextureManager::from_manifest();
+
+        let mut lighting = Lighting::default();
+        lighting.set_lights(&level, block_size);
+
+        let block = block_size as f32;
+        let decorations = level.sprites.iter().map(|placement| {
+            if !texture_cache.has_sprite(&placement.name) {
+                panic!("{} places unknown sprite `{}`", path, placement.name);
+            }
+            Sprite {
+                pos: placement.pos * block,
+                z: 0.0,
+                size: placement.size * block,
+                texture: placement.name.clone(),
+                facing: placement.facing,
+                spawned: 0.0,
+            }
+        }).collect();
+
+        let mut crumbs = Vec::new();
+        let mut keys = Vec::new();
+        for (j, row) in level.maze.iter().enumerate() {
+            for (i, &c) in row.iter().enumerate() {
+                match c {
+                    'g' => crumbs.push((i, j)),
+                    'k' => keys.push((i, j)),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut decals = Decals::default();
+        for decal in &level.decals {
+            if !texture_cache.has_decal(&decal.texture) {
+                panic!("{} places unknown decal `{}`", path, decal.texture);
+            }
+            decals.add(decal.clone());
+        }
+
+        Scene { level, doors, texture_cache, lighting, decorations, crumbs, keys, decals, block_size }
+    }
+
+    // Every billboard: the decorations and the pickups still lying around
+    pub fn sprites(&self) -> Vec<Sprite> {
+        let mut sprites = self.decorations.clone();
+        sprites.extend(self.crumbs.iter().map(|&(i, j)| pickup_sprite(i, j, self.block_size, "crumb")));
+        sprites.extend(self.keys.iter().map(|&(i, j)| pickup_sprite(i, j, self.block_size, "key")));
+        sprites
+    }
+
+    // Draw the 3D view from `player` at `time` seconds into the game
+    pub fn render(&self, framebuffer: &mut Framebuffer, player: &Player, time: f32) {
+        render_world(
+            framebuffer,
+            &self.level,
+            &self.doors,
+            self.block_size,
+            player,
+            &self.texture_cache,
+            &self.lighting,
+            &self.sprites(),
+            &self.decals,
+            time,
+        );
+    }
+}
+
+// Billboard for a pickup lying in the middle of cell (i, j)
+fn pickup_sprite(i: usize, j: usize, block_size: usize, texture: &str) -> Sprite {
+    let block = block_size as f32;
+    Sprite {
+        pos: Vector2::new((i as f32 + 0.5) * block, (j as f32 + 0.5) * block),
+        z: 0.0,
+        size: block * CRUMB_SIZE,
+        texture: texture.to_string(),
+        facing: 0.0,
+        spawned: 0.0,
+    }
+}
@@ -0,0 +1,150 @@
+// sprites.rs
+
+use std::f32::consts::PI;
+
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::gfx::Vector2;
+use crate::lighting::Lighting;
+use crate::textures::TextureManager;
+
+// Texels with less alpha than this are holes in the billboard, and do
+// not hide sprites behind see-through walls
+pub const ALPHA_CUTOFF: u8 = 128;
+
+// Frame layout and playback of a sprite texture, from its entry in
+// assets/tiles.txt. Frames run left to right; with 8 directions, row k
+// shows the sprite seen from k * 45 degrees around its facing.
+pub struct SpriteSheet {
+  pub frames: usize,
+  pub directions: usize,
+  pub fps: f32,
+  pub looping: bool,
+  pub bob: f32, // bobbing height as a fraction of the sprite size
+}
+
+impl SpriteSheet {
+  // Parse `frames=8 directions=8 fps=10 once bob=0.2` style options
+  pub fn parse(options: &[&str]) -> Option<Self> {
+    let mut sheet = SpriteSheet { frames: 1, directions: 1, fps: 0.0, looping: true, bob: 0.0 };
+    for option in options {
+      match option.split_once('=') {
+        Some(("frames", n)) => sheet.frames = n.parse().ok().filter(|&n| n > 0)?,
+        Some(("directions", n)) => sheet.directions = n.parse().ok().filter(|&n| n == 1 || n == 8)?,
+        Some(("fps", n)) => sheet.fps = n.parse().ok()?,
+        Some(("bob", n)) => sheet.bob = n.parse().ok()?,
+        None if *option == "once" => sheet.looping = false,
+        _ => return None,
+      }
+    }
+    Some(sheet)
+  }
+
+  // Animation frame `elapsed` seconds after the sprite spawned
+  pub fn frame_at(&self, elapsed: f32) -> usize {
+    let frame = (elapsed.max(0.0) * self.fps) as usize;
+    if self.looping { frame % self.frames } else { frame.min(self.frames - 1) }
+  }
+
+  // Row to draw for a viewer at `view_angle` (sprite to viewer) when the
+  // sprite faces `facing`
+  pub fn direction_for(&self, view_angle: f32, facing: f32) -> usize {
+    if self.directions == 1 {
+      return 0;
+    }
+    let step = 2.0 * PI / self.directions as f32;
+    let relative = (view_angle - facing).rem_euclid(2.0 * PI);
+    ((relative / step).round() as usize) % self.directions
+  }
+}
+
+#[derive(Clone)]
+pub struct Sprite {
+  pub pos: Vector2,    // world position of the sprite's foot
+  pub z: f32,          // height of the sprite's bottom above the floor
+  pub size: f32,       // height in world units; width follows the texture
+  pub texture: String, // sprite name in assets/tiles.txt
+  pub facing: f32,     // angle the sprite faces, for directional sheets
+  pub spawned: f32,    // time the animation started, in seconds
+}
+
+// Draw camera-facing billboards back to front. `depth_buffer` holds the
+// wall depth of every screen pixel, so walls occlude sprites per pixel.
+#[allow(clippy::too_many_arguments)]
+pub fn render_sprites(
+  framebuffer: &mut Framebuffer,
+  camera: &Camera,
+  sprites: &[Sprite],
+  texture_cache: &TextureManager,
+  lighting: &Lighting,
+  depth_buffer: &[f32],
+  block_size: usize,
+  time: f32,
+) {
+  let screen_width = framebuffer.width as f32;
+  let screen_height = framebuffer.height as f32;
+
+  // Sprites behind the camera are dropped, the rest drawn farthest first
+  let mut visible: Vec<(&Sprite, f32, f32)> = sprites
+    .iter()
+    .filter_map(|sprite| {
+      camera.project(sprite.pos).map(|(screen_x, depth)| (sprite, screen_x, depth))
+    })
+    .collect();
+  visible.sort_by(|a, b| b.2.total_cmp(&a.2));
+
+  for (sprite, screen_x, depth) in visible {
+    let (texture, sheet) = texture_cache.sprite(&sprite.texture);
+
+    // Pick the cell of the sheet for this moment and view direction
+    let frame = sheet.frame_at(time - sprite.spawned);
+    let view_angle = (camera.pos.y - sprite.pos.y).atan2(camera.pos.x - sprite.pos.x);
+    let direction = sheet.direction_for(view_angle, sprite.facing);
+    let frame_width = texture.width() as f32 / sheet.frames as f32;
+    let frame_height = texture.height() as f32 / sheet.directions as f32;
+
+    let height = camera.project_size(sprite.size, depth);
+    let width = height * frame_width / frame_height;
+    if height < 1.0 {
+      continue;
+    }
+
+    // bob between the base height and `bob` sprite heights above it,
+    // out of phase between sprites
+    let phase = (sprite.pos.x + sprite.pos.y) * 0.01;
+    let bob = sheet.bob * sprite.size * ((time * PI + phase).sin() + 1.0) / 2.0;
+
+    let floor_y = camera.horizon + camera.project_size(camera.eye * block_size as f32, depth);
+    let bottom = floor_y - camera.project_size(sprite.z + bob, depth);
+    let top = bottom - height;
+    let left = screen_x - width / 2.0;
+
+    // Clip to the screen in floating point before converting to pixels
+    let x_start = left.max(0.0) as u32;
+    let x_end = (left + width).min(screen_width).max(0.0) as u32;
+    let y_start = top.max(0.0) as u32;
+    let y_end = bottom.min(screen_height).max(0.0) as u32;
+
+    let light = lighting.illumination_at(sprite.pos);
+
+    for x in x_start..x_end {
+      let u = (frame as f32 + (x as f32 + 0.5 - left) / width) / sheet.frames as f32;
+
+      for y in y_start..y_end {
+        // Depth test: only draw where the sprite is closer than the wall
+        if depth >= depth_buffer[(y * framebuffer.width + x) as usize] {
+          continue;
+        }
+        let v = (direction as f32 + (y as f32 + 0.5 - top) / height) / sheet.directions as f32;
+        // the whole sheet is `directions` frames tall on screen
+        let color = texture.sample(u, v, height * sheet.directions as f32);
+        if color.a < ALPHA_CUTOFF {
+          continue;
+        }
+
+        framebuffer.set_current_color(lighting.apply(color, depth, 1.0, light));
+        framebuffer.set_pixel(x, y);
+      }
+    }
+  }
+}
@@ -1,60 +1,292 @@
 // textures.rs
 
-use raylib::prelude::*;
-use std::slice;
+use png::{Decoder, DecodingError, Transformations};
+use std::collections::HashMap;
+use std::fs::File;
 
-pub struct TextureManager {
-    wall_image: Image,       // Store wall image for pixel access
-    wall_texture: Texture2D, // Store GPU texture for rendering
+use crate::caster::Face;
+use crate::gfx::Color;
+use crate::sprites::SpriteSheet;
+
+const MANIFEST: &str = "assets/tiles.txt";
+
+// One level of a mip chain
+struct MipLevel {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
 }
 
-impl TextureManager {
-    pub fn new(rl: &mut RaylibHandle, thread: &RaylibThread) -> Self {
-        // Load wall texture
-        let wall_image = Image::load_image("assets/wall.png")
-            .expect("Failed to load wall.png");
-        let wall_texture = rl.load_texture(thread, "assets/wall.png")
-            .expect("Failed to load wall texture");
-
-        TextureManager { 
-            wall_image, 
-            wall_texture 
+impl MipLevel {
+    // 2x2 box filter; odd or 1-pixel dimensions clamp to the edge
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+
+        for y in 0..height {
+            for x in 0..width {
+                let x0 = (x * 2).min(self.width - 1);
+                let x1 = (x * 2 + 1).min(self.width - 1);
+                let y0 = (y * 2).min(self.height - 1);
+                let y1 = (y * 2 + 1).min(self.height - 1);
+                let texels = [
+                    self.pixels[y0 * self.width + x0],
+                    self.pixels[y0 * self.width + x1],
+                    self.pixels[y1 * self.width + x0],
+                    self.pixels[y1 * self.width + x1],
+                ];
+                let avg = |channel: fn(&Color) -> u8| {
+                    (texels.iter().map(|c| channel(c) as u32).sum::<u32>() / 4) as u8
+                };
+                pixels.push(Color::new(avg(|c| c.r), avg(|c| c.g), avg(|c| c.b), avg(|c| c.a)));
+            }
         }
+
+        MipLevel { width, height, pixels }
     }
+}
 
-    pub fn get_wall_pixel_color(&self, tx: u32, ty: u32) -> Color {
-        let x = tx.min(self.wall_image.width as u32 - 1) as i32;
-        let y = ty.min(self.wall_image.height as u32 - 1) as i32;
-        get_pixel_color(&self.wall_image, x, y)
+// CPU-side texture with its full mip chain, generated at load time
+pub struct Texture {
+    levels: Vec<MipLevel>,
+}
+
+impl Texture {
+    // `pixels` row by row from the top
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        let mut levels = vec![MipLevel { width, height, pixels }];
+
+        loop {
+            let last = &levels[levels.len() - 1];
+            if last.width == 1 && last.height == 1 {
+                break;
+            }
+            let next = last.downsample();
+            levels.push(next);
+        }
+
+        Texture { levels }
     }
 
-    pub fn get_wall_texture(&self) -> &Texture2D {
-        &self.wall_texture
+    pub fn width(&self) -> usize {
+        self.levels[0].width
+    }
+
+    pub fn height(&self) -> usize {
+        self.levels[0].height
+    }
+
+    // Sample at (u, v), wrapping outside [0, 1). `screen_size` is how many
+    // pixels tall the whole texture is on screen; the mip level is picked
+    // so that one texel covers roughly one pixel.
+    pub fn sample(&self, u: f32, v: f32, screen_size: f32) -> Color {
+        let texels_per_pixel = self.height() as f32 / screen_size.max(1.0);
+        let level = if texels_per_pixel > 1.0 { texels_per_pixel.log2() as usize } else { 0 };
+        let mip = &self.levels[level.min(self.levels.len() - 1)];
+
+        let x = ((u.rem_euclid(1.0) * mip.width as f32) as usize).min(mip.width - 1);
+        let y = ((v.rem_euclid(1.0) * mip.height as f32) as usize).min(mip.height - 1);
+        mip.pixels[y * mip.width + x]
     }
 }
 
-fn get_pixel_color(image: &Image, x: i32, y: i32) -> Color {
-    let width = image.width as usize;
-    let height = image.height as usize;
+// Textures of one wall tile: one image for every face, plus optional
+// per-face overrides indexed by `face_index`
+struct TileTextures {
+    default: usize,
+    faces: [Option<usize>; 4],
+}
 
-    if x < 0 || y < 0 || x as usize >= width || y as usize >= height {
-        return Color::GRAY; // Fallback color
+impl TileTextures {
+    fn image(&self, face: Face) -> usize {
+        self.faces[face_index(face)].unwrap_or(self.default)
     }
+}
+
+fn face_index(face: Face) -> usize {
+    match face {
+        Face::North => 0,
+        Face::South => 1,
+        Face::East => 2,
+        Face::West => 3,
+    }
+}
+
+pub struct TextureManager {
+    images: Vec<Texture>, // every image in the manifest, loaded once per path
+    walls: HashMap<char, TileTextures>,
+    floors: HashMap<char, usize>,
+    ceilings: HashMap<char, usize>,
+    sprites: HashMap<String, (usize, SpriteSheet)>,
+    sky: Option<usize>,
+    decals: HashMap<String, usize>,
+}
+
+impl TextureManager {
+    // Every texture in the manifest
+    pub fn from_manifest() -> Self {
+        let manifest = std::fs::read_to_string(MANIFEST)
+            .unwrap_or_else(|_| panic!("Failed to read {}", MANIFEST));
+
+        let mut manager = TextureManager {
+            images: Vec::new(),
+            walls: HashMap::new(),
+            floors: HashMap::new(),
+            ceilings: HashMap::new(),
+            sprites: HashMap::new(),
+            sky: None,
+            decals: HashMap::new(),
+        };
+        let mut paths: HashMap<String, usize> = HashMap::new();
+
+        for (n, line) in manifest.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
 
-    let x = x as usize;
-    let y = y as usize;
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let malformed = format!("{}:{}: malformed entry `{}`", MANIFEST, n + 1, line);
 
-    let data_len = width * height * 4;
+            // sprite <name> <path> [option...]
+            if parts[0] == "sprite" {
+                let [_, name, path, options @ ..] = parts.as_slice() else { panic!("{}", malformed) };
+                let sheet = SpriteSheet::parse(options).unwrap_or_else(|| panic!("{}", malformed));
+                let image = manager.load(&mut paths, path);
+                manager.sprites.insert(name.to_string(), (image, sheet));
+                continue;
+            }
 
-    unsafe {
-        let data = slice::from_raw_parts(image.data as *const u8, data_len);
+            // decal <name> <path>
+            if parts[0] == "decal" {
+                let [_, name, path] = parts.as_slice() else { panic!("{}", malformed) };
+                let image = manager.load(&mut paths, path);
+                manager.decals.insert(name.to_string(), image);
+                continue;
+            }
 
-        let idx = (y * width + x) * 4;
+            // sky <path>
+            if parts[0] == "sky" {
+                let [_, path] = parts.as_slice() else { panic!("{}", malformed) };
+                manager.sky = Some(manager.load(&mut paths, path));
+                continue;
+            }
 
-        if idx + 3 >= data_len {
-            return Color::GRAY; // Fallback color
+            let (kind, name, face, path) = match parts.as_slice() {
+                [kind, name, path] => (*kind, *name, None, *path),
+                [kind, name, face, path] => {
+                    (*kind, *name, Some(Face::from_name(face).unwrap_or_else(|| panic!("{}", malformed))), *path)
+                }
+                _ => panic!("{}", malformed),
+            };
+            // tiles and floor / ceiling keys are single maze characters
+            let key = name.chars().next().unwrap_or(' ');
+
+            let image = manager.load(&mut paths, path);
+
+            match (kind, face) {
+                ("wall", None) => {
+                    let tile = manager.walls.entry(key).or_insert(TileTextures { default: image, faces: [None; 4] });
+                    tile.default = image;
+                }
+                ("wall", Some(face)) => {
+                    let tile = manager.walls.entry(key).or_insert(TileTextures { default: image, faces: [None; 4] });
+                    tile.faces[face_index(face)] = Some(image);
+                }
+                ("floor", None) => { manager.floors.insert(key, image); }
+                ("ceiling", None) => { manager.ceilings.insert(key, image); }
+                _ => panic!("{}", malformed),
+            }
+        }
+
+        if !manager.walls.contains_key(&'*') || !manager.floors.contains_key(&'.') || !manager.ceilings.contains_key(&'.') {
+            panic!("{} needs `wall *`, `floor .` and `ceiling .` entries", MANIFEST);
+        }
+
+        manager
+    }
+
+    // Index of the texture at `path`, loading it the first time it is seen
+    fn load(&mut self, paths: &mut HashMap<String, usize>, path: &str) -> usize {
+        if let Some(&index) = paths.get(path) {
+            return index;
         }
+        let texture = decode_png(path)
+            .unwrap_or_else(|err| panic!("Failed to load {}: {}", path, err));
+        self.images.push(texture);
+        paths.insert(path.to_string(), self.images.len() - 1);
+        self.images.len() - 1
+    }
+
+    // Texture for the given face of a wall tile; tiles without an entry use '*'
+    pub fn wall_texture(&self, tile: char, face: Face) -> &Texture {
+        let textures = self.walls.get(&tile).unwrap_or_else(|| &self.walls[&'*']);
+        &self.images[textures.image(face)]
+    }
+
+    // u and v are texture coordinates in [0, 1); `screen_size` is the
+    // on-screen height of the stake and selects the mip level
+    pub fn get_wall_pixel_color(&self, tile: char, face: Face, u: f32, v: f32, screen_size: f32) -> Color {
+        self.wall_texture(tile, face).sample(u, v, screen_size)
+    }
+
+    // Panorama shown above outdoor cells, if the manifest has one
+    pub fn sky(&self) -> Option<&Texture> {
+        self.sky.map(|index| &self.images[index])
+    }
 
-        Color::new(data[idx], data[idx + 1], data[idx + 2], data[idx + 3])
+    pub fn has_sprite(&self, name: &str) -> bool {
+        self.sprites.contains_key(name)
     }
+
+    // Sprite sheet texture and its frame layout
+    pub fn sprite(&self, name: &str) -> (&Texture, &SpriteSheet) {
+        let (index, sheet) = self.sprites.get(name)
+            .unwrap_or_else(|| panic!("No sprite `{}` in {}", name, MANIFEST));
+        (&self.images[*index], sheet)
+    }
+
+    pub fn has_decal(&self, name: &str) -> bool {
+        self.decals.contains_key(name)
+    }
+
+    // None for names the manifest doesn't have
+    pub fn decal(&self, name: &str) -> Option<&Texture> {
+        self.decals.get(name).map(|&index| &self.images[index])
+    }
+
+    // Unknown floor / ceiling keys fall back to '.'; `screen_size` is the
+    // on-screen size of one cell at that depth
+    pub fn get_floor_pixel_color(&self, key: char, u: f32, v: f32, screen_size: f32) -> Color {
+        self.images[lookup(&self.floors, key)].sample(u, v, screen_size)
+    }
+
+    pub fn get_ceiling_pixel_color(&self, key: char, u: f32, v: f32, screen_size: f32) -> Color {
+        self.images[lookup(&self.ceilings, key)].sample(u, v, screen_size)
+    }
+}
+
+fn lookup(images: &HashMap<char, usize>, key: char) -> usize {
+    images.get(&key).copied().unwrap_or(images[&'.'])
+}
+
+// Any PNG as RGBA: palettes and gray are expanded, 16-bit channels cut to 8
+fn decode_png(path: &str) -> Result<Texture, DecodingError> {
+    let mut decoder = Decoder::new(File::open(path)?);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+    let mut reader = decoder.read_info()?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut data)?;
+    let pixels = data[..frame.buffer_size()]
+        .chunks_exact(frame.color_type.samples())
+        .map(|p| match *p {
+            [gray] => Color::new(gray, gray, gray, 255),
+            [gray, alpha] => Color::new(gray, gray, gray, alpha),
+            [r, g, b] => Color::new(r, g, b, 255),
+            [r, g, b, a] => Color::new(r, g, b, a),
+            _ => unreachable!("PNG pixels have 1 to 4 samples"),
+        })
+        .collect();
+    Ok(Texture::from_pixels(frame.width as usize, frame.height as usize, pixels))
 }
@@ -0,0 +1,92 @@
+// viewmodel.rs
+
+use crate::framebuffer::Framebuffer;
+use crate::gfx::Vector2;
+use crate::lighting::Lighting;
+use crate::player::Player;
+use crate::sprites::ALPHA_CUTOFF;
+use crate::textures::TextureManager;
+
+// On-screen height of the view model, as a fraction of the screen height
+const SIZE: f32 = 0.45;
+// How far walking swings the model, as fractions of the screen size
+const BOB_X: f32 = 0.02;
+const BOB_Y: f32 = 0.015;
+// Screen fraction the model lags per block per second of velocity
+const SWAY: f32 = 0.01;
+
+// First-person model (a hand, a lantern) drawn over the 3D view, held to
+// the lower right. It swings with the player's steps and lags behind
+// their movement.
+pub struct ViewModel {
+  pub sprite: String, // sprite name in assets/tiles.txt
+  pub visible: bool,
+  sway: Vector2, // current lag, in screen fractions
+}
+
+impl ViewModel {
+  pub fn new(sprite: &str) -> Self {
+    ViewModel { sprite: sprite.to_string(), visible: true, sway: Vector2::new(0.0, 0.0) }
+  }
+
+  // Ease the sway towards the player's velocity relative to the view:
+  // moving forward dips the model, moving sideways pushes it the other way
+  pub fn update(&mut self, player: &Player, dt: f32) {
+    let forward = player.velocity.x * player.a.cos() + player.velocity.y * player.a.sin();
+    let sideways = player.velocity.y * player.a.cos() - player.velocity.x * player.a.sin();
+    let target = Vector2::new(-sideways * SWAY, forward.abs() * SWAY);
+    let ease = (8.0 * dt).min(1.0);
+    self.sway.x += (target.x - self.sway.x) * ease;
+    self.sway.y += (target.y - self.sway.y) * ease;
+  }
+
+  pub fn render(
+    &self,
+    framebuffer: &mut Framebuffer,
+    player: &Player,
+    texture_cache: &TextureManager,
+    lighting: &Lighting,
+    time: f32,
+  ) {
+    if !self.visible {
+      return;
+    }
+    let (texture, sheet) = texture_cache.sprite(&self.sprite);
+    let frame = sheet.frame_at(time);
+    let frame_width = texture.width() as f32 / sheet.frames as f32;
+    let frame_height = texture.height() as f32 / sheet.directions as f32;
+
+    let screen_width = framebuffer.width as f32;
+    let screen_height = framebuffer.height as f32;
+    let height = screen_height * SIZE;
+    let width = height * frame_width / frame_height;
+
+    // a figure eight: side to side once per two steps, up and down every step
+    let swing_x = (player.bob_phase / 2.0).sin() * BOB_X * player.walking;
+    let swing_y = player.bob_phase.sin().abs() * BOB_Y * player.walking;
+    let left = screen_width * (0.62 + swing_x + self.sway.x);
+    // the bottom of the model stays below the screen edge
+    let top = screen_height * (1.0 - SIZE + 0.05 + swing_y + self.sway.y);
+
+    // lit by what reaches the player, never fogged
+    let light = lighting.illumination_at(player.pos);
+
+    let x_start = left.max(0.0) as u32;
+    let x_end = (left + width).min(screen_width).max(0.0) as u32;
+    let y_start = top.max(0.0) as u32;
+    let y_end = (top + height).min(screen_height).max(0.0) as u32;
+
+    for x in x_start..x_end {
+      let u = (frame as f32 + (x as f32 + 0.5 - left) / width) / sheet.frames as f32;
+      for y in y_start..y_end {
+        let v = (y as f32 + 0.5 - top) / height / sheet.directions as f32;
+        let color = texture.sample(u, v, height * sheet.directions as f32);
+        if color.a < ALPHA_CUTOFF {
+          continue;
+        }
+        framebuffer.set_current_color(lighting.apply(color, 0.0, 1.0, light));
+        framebuffer.set_pixel(x, y);
+      }
+    }
+  }
+}